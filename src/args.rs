@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+
+use syn::{
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    Error, Lit, LitStr, MetaNameValue, Token,
+};
+
+/// Selects the device description for all expansions without a `path`
+/// argument, e.g. to select a board from the build script or the command line
+const PATH_ENV: &str = "DEVICE_CONFIG_PATH";
+
+/// The arguments of the `device_config` attribute,
+/// e.g. `#[device_config(path = "boards/rev_b.yaml")]`
+/// or `#[device_config(yaml = r#"..."#)]`
+#[derive(Default)]
+pub(crate) struct Args {
+    /// Path of the device description relative to the crate manifest
+    pub path: Option<LitStr>,
+    /// The device description itself
+    pub yaml: Option<LitStr>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = Args::default();
+        let arguments = Punctuated::<MetaNameValue, Token![,]>::parse_terminated(input)?;
+        for argument in arguments {
+            let value = match &argument.lit {
+                Lit::Str(value) => value.clone(),
+                other => return Err(Error::new_spanned(other, "expected a string literal")),
+            };
+            let slot = if argument.path.is_ident("path") {
+                &mut args.path
+            } else if argument.path.is_ident("yaml") {
+                &mut args.yaml
+            } else {
+                return Err(Error::new_spanned(
+                    &argument.path,
+                    "unknown argument, expected 'path' or 'yaml'",
+                ));
+            };
+            if slot.replace(value).is_some() {
+                return Err(Error::new_spanned(&argument.path, "duplicate argument"));
+            }
+            if args.path.is_some() && args.yaml.is_some() {
+                return Err(Error::new_spanned(
+                    &argument.path,
+                    "'path' and 'yaml' cannot be used together",
+                ));
+            }
+        }
+        Ok(args)
+    }
+}
+
+impl Args {
+    /// Unless the description is given inline with the `yaml` argument,
+    /// it is read from the first of
+    /// 1. the `path` argument
+    /// 2. the path in the `DEVICE_CONFIG_PATH` environment variable
+    /// 3. `device.yaml` in the project root
+    ///
+    /// Relative paths are resolved against the manifest directory of the
+    /// crate that uses the attribute.
+    pub(crate) fn config_path(&self) -> PathBuf {
+        let path = match (&self.path, std::env::var(PATH_ENV)) {
+            (Some(path), _) => PathBuf::from(path.value()),
+            (None, Ok(path)) => PathBuf::from(path),
+            (None, Err(_)) => {
+                let mut path =
+                    project_root::get_project_root().expect("Unable to find project root");
+                path.push("device.yaml");
+                return path;
+            }
+        };
+        if path.is_absolute() {
+            return path;
+        }
+        let mut manifest_dir = PathBuf::from(
+            std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is not set"),
+        );
+        manifest_dir.push(path);
+        manifest_dir
+    }
+    /// Cargo only reruns the expansion for environment variables that are
+    /// read by the compiler, so the variable is read in an unused constant
+    /// whenever it can select the description.
+    pub(crate) fn env_trigger(&self) -> Option<syn::Item> {
+        match (&self.yaml, &self.path) {
+            (None, None) => Some(syn::parse_quote!(
+                const _: Option<&str> = option_env!(#PATH_ENV);
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn args_test() {
+    let args: Args = syn::parse_quote!(path = "boards/rev_b.yaml");
+    assert_eq!(args.path.unwrap().value(), "boards/rev_b.yaml");
+    assert!(syn::parse_str::<Args>("").unwrap().path.is_none());
+    assert!(syn::parse_str::<Args>("file = \"device.yaml\"").is_err());
+    assert!(syn::parse_str::<Args>("path = 5").is_err());
+    assert!(syn::parse_str::<Args>("path = \"a.yaml\", path = \"b.yaml\"").is_err());
+    let args: Args = syn::parse_quote!(yaml = r#"kind: stm32f1xx"#);
+    assert_eq!(args.yaml.unwrap().value(), "kind: stm32f1xx");
+    assert!(syn::parse_str::<Args>("path = \"a.yaml\", yaml = \"kind: stm32f1xx\"").is_err());
+}
+
+#[test]
+fn env_trigger_test() {
+    let args: Args = syn::parse_quote!(path = "boards/rev_b.yaml");
+    assert!(args.env_trigger().is_none());
+    let trigger = syn::parse_str::<Args>("").unwrap().env_trigger();
+    assert_eq!(
+        quote::quote!(#trigger).to_string(),
+        quote::quote!(
+            const _: Option<&str> = option_env!("DEVICE_CONFIG_PATH");
+        )
+        .to_string()
+    );
+}
@@ -0,0 +1,180 @@
+//! Generation that is shared by the device backends
+use std::collections::BTreeMap;
+
+use quote::{format_ident, quote};
+use syn::{parse_quote, Ident, Item, ItemStruct, Stmt};
+
+use super::{Duty, Hertz, Priority};
+
+/// The binding of a component that is returned from the init function
+/// with the type of its field
+pub(super) struct Initialized {
+    pub ty: syn::Type,
+    pub ident: Ident,
+}
+
+/// The fields of a group struct for the components
+pub(super) fn fields(list: &[Initialized]) -> Vec<(Ident, syn::Type)> {
+    list.iter()
+        .map(|c| (c.ident.clone(), c.ty.clone()))
+        .collect()
+}
+
+/// The initialized components of a device, grouped by kind
+pub(super) struct ComponentGroups {
+    pub inputs: Vec<(Ident, syn::Type)>,
+    pub outputs: Vec<(Ident, syn::Type)>,
+    pub timers: Vec<(Ident, syn::Type)>,
+    pub pwm: Vec<(Ident, syn::Type)>,
+    pub serial: Vec<(Ident, syn::Type)>,
+}
+
+/// Defines the struct of the components, named after the configured struct
+/// (e.g. `BoardComponents` for `Board`). The components are grouped by kind
+/// into sub-structs with one field per component.
+/// Returns the struct definitions, the name of the components struct and
+/// the expression that moves the bindings of the init block into it.
+fn components_struct(
+    item_struct: &ItemStruct,
+    groups: ComponentGroups,
+) -> (Vec<Item>, Ident, syn::Expr) {
+    let vis = &item_struct.vis;
+    let prefix = &item_struct.ident;
+    let components_struct = format_ident!("{}Components", prefix);
+    let mut items = Vec::new();
+    let mut fields = Vec::new();
+    let mut group_structs = Vec::new();
+    let mut group_inits = Vec::new();
+    let mut group = |field: &str, name: &str, components: Vec<(Ident, syn::Type)>| {
+        let field = format_ident!("{}", field);
+        let group_struct = format_ident!("{}{}", prefix, name);
+        let (ids, tys): (Vec<Ident>, Vec<syn::Type>) = components.into_iter().unzip();
+        items.push(parse_quote!(
+            #vis struct #group_struct {
+                #(pub #ids: #tys,)*
+            }
+        ));
+        fields.push(field);
+        group_structs.push(group_struct.clone());
+        group_inits.push(quote!(#group_struct { #(#ids,)* }));
+    };
+    group("inputs", "Inputs", groups.inputs);
+    group("outputs", "Outputs", groups.outputs);
+    group("timers", "Timers", groups.timers);
+    group("pwm", "Pwm", groups.pwm);
+    group("serial", "Serial", groups.serial);
+    items.push(parse_quote!(
+        #vis struct #components_struct {
+            #(pub #fields: #group_structs,)*
+        }
+    ));
+    let components = parse_quote!(
+        #components_struct {
+            #(#fields: #group_inits,)*
+        }
+    );
+    (items, components_struct, components)
+}
+
+/// Moves the components into a static struct and returns a reference to it,
+/// see [`components_struct`]. The peripherals of a chip can only be taken
+/// once, so the init function runs once as well.
+/// Returns the init statements, the return type and the struct definitions.
+pub(super) fn static_init_and_return(
+    init_block: Vec<Stmt>,
+    item_struct: &ItemStruct,
+    groups: ComponentGroups,
+) -> (Vec<Stmt>, syn::Type, Vec<Item>) {
+    const COMPONENTS_IDENT: &str = "COMPONENTS";
+    let (items, components_struct, components) = components_struct(item_struct, groups);
+    let static_name = format_ident!("{}", COMPONENTS_IDENT);
+    let mut stmts = init_block;
+    stmts.push(parse_quote!(
+        use core::mem::MaybeUninit;
+    ));
+    stmts.push(parse_quote!(
+        static mut #static_name: MaybeUninit<#components_struct> = MaybeUninit::uninit();
+    ));
+    stmts.push(parse_quote!(
+        unsafe {
+            #static_name.write(#components)
+        }
+    ));
+    let return_type = parse_quote!(&'static mut #components_struct);
+    (stmts, return_type, items)
+}
+
+/// Returns the components by value, see [`components_struct`]. For devices
+/// whose init function can run more than once (e.g. the mocks of the host),
+/// each call then owns its components.
+/// Returns the init statements, the return type and the struct definitions.
+pub(super) fn init_and_return(
+    init_block: Vec<Stmt>,
+    item_struct: &ItemStruct,
+    groups: ComponentGroups,
+) -> (Vec<Stmt>, syn::Type, Vec<Item>) {
+    let (items, components_struct, components) = components_struct(item_struct, groups);
+    let mut stmts = init_block;
+    stmts.push(Stmt::Expr(components));
+    (stmts, parse_quote!(#components_struct), items)
+}
+
+/// The duty of a pwm channel scaled to the maximum duty of the timer
+/// expand: (max_duty as u32 * duty / scale) as u16
+pub(super) fn scaled_duty(max_duty: syn::Expr, duty: Duty) -> syn::Expr {
+    let duty = duty.0;
+    let scale = Duty::SCALE;
+    parse_quote!((#max_duty as u32 * #duty / #scale) as u16)
+}
+
+/// Constants with the frequencies the hal configures, named after the
+/// clocks (e.g. `SYSCLK_HZ`)
+pub(super) fn frequency_consts(frequencies: &[(&str, Hertz)]) -> Vec<syn::ImplItem> {
+    frequencies
+        .iter()
+        .map(|(name, frequency)| {
+            let name = format_ident!("{}_HZ", name);
+            let frequency = frequency.0 as u32;
+            parse_quote!(const #name: u32 = #frequency;)
+        })
+        .collect()
+}
+
+/// Unmasks the interrupts in the NVIC of the peripheral access crate 'pac'.
+/// Configured priorities are set before any interrupt is unmasked.
+pub(super) fn nvic_unmasks(
+    pac: &syn::Path,
+    interrupts: &BTreeMap<String, Option<Priority>>,
+    implemented_bits: u8,
+) -> Vec<Stmt> {
+    let mut stmts = Vec::new();
+    let priorities: Vec<(Ident, u8)> = interrupts
+        .iter()
+        .filter_map(|(interrupt, priority)| {
+            priority.map(|priority| {
+                (
+                    format_ident!("{}", interrupt),
+                    priority.register_value(implemented_bits),
+                )
+            })
+        })
+        .collect();
+    if !priorities.is_empty() {
+        let nvic = format_ident!("nvic");
+        stmts.push(parse_quote!(
+            let mut #nvic = #pac::CorePeripherals::steal().NVIC;
+        ));
+        for (interrupt, priority) in priorities {
+            stmts.push(parse_quote!(
+                #nvic.set_priority(#pac::Interrupt::#interrupt, #priority);
+            ));
+        }
+    }
+    for interrupt in interrupts.keys() {
+        let interrupt = format_ident!("{}", interrupt);
+        stmts.push(parse_quote!(
+            #pac::NVIC::unmask(#pac::Interrupt::#interrupt);
+        ));
+    }
+    stmts
+}
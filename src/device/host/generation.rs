@@ -0,0 +1,200 @@
+use quote::format_ident;
+use syn::{parse_quote, Item, ItemStruct, Stmt};
+
+use crate::device::{
+    generation::{fields, frequency_consts, init_and_return, ComponentGroups, Initialized},
+    PeripheralId,
+};
+
+use super::{Gpio, HostPeripherals};
+
+pub struct DeviceInit {
+    init_block: Vec<syn::Stmt>,
+}
+
+impl DeviceInit {
+    /// Creates the mock without expectations.
+    /// expand: let binding = constructor;
+    fn init_mock(&mut self, binding: &str, ty: syn::Type, constructor: syn::Expr) -> Initialized {
+        let ident = format_ident!("{}", binding);
+        self.init_block.push(parse_quote!(
+            let #ident = #constructor;
+        ));
+        Initialized { ty, ident }
+    }
+    fn gpios(
+        &mut self,
+        peripheral_config: &HostPeripherals,
+    ) -> (Vec<Initialized>, Vec<Initialized>) {
+        let mut gpio = |gpio: &Gpio| {
+            self.init_mock(
+                &gpio.binding(),
+                parse_quote!(embedded_hal_mock::eh0::digital::Mock),
+                parse_quote!(embedded_hal_mock::eh0::digital::Mock::new(&[])),
+            )
+        };
+        let inputs = peripheral_config.gpio.input.iter().map(&mut gpio).collect();
+        let outputs = peripheral_config
+            .gpio
+            .output
+            .iter()
+            .map(&mut gpio)
+            .collect();
+        (inputs, outputs)
+    }
+    fn timers(&mut self, peripheral_config: &HostPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for timer in &peripheral_config.timer {
+            idents.push(self.init_mock(
+                timer.id.lower(),
+                parse_quote!(embedded_hal_mock::eh0::delay::NoopDelay),
+                parse_quote!(embedded_hal_mock::eh0::delay::NoopDelay::new()),
+            ));
+        }
+        idents
+    }
+    /// The pin mocks implement the pwm pin, one per channel
+    fn pwms(&mut self, peripheral_config: &HostPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for pwm in &peripheral_config.pwm {
+            let channels = pwm.pins.len();
+            let mocks = (0..channels).map(|_| -> syn::Expr {
+                parse_quote!(embedded_hal_mock::eh0::digital::Mock::new(&[]))
+            });
+            idents.push(self.init_mock(
+                &pwm.binding(),
+                parse_quote!([embedded_hal_mock::eh0::digital::Mock; #channels]),
+                parse_quote!([#(#mocks),*]),
+            ));
+        }
+        idents
+    }
+    fn serials(&mut self, peripheral_config: &HostPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for serial in &peripheral_config.serial {
+            idents.push(self.init_mock(
+                &serial.binding(),
+                parse_quote!(embedded_hal_mock::eh0::serial::Mock<u8>),
+                parse_quote!(embedded_hal_mock::eh0::serial::Mock::<u8>::new(&[])),
+            ));
+        }
+        idents
+    }
+
+    /// The mocks are created anew in every call, so tests that run in
+    /// parallel each get their own components
+    pub(crate) fn get_init_block(
+        peripheral_config: &HostPeripherals,
+        item_struct: &ItemStruct,
+    ) -> (Vec<Stmt>, syn::Type, Vec<Item>) {
+        let mut device_init = DeviceInit {
+            init_block: Vec::new(),
+        };
+        let (inputs, outputs) = device_init.gpios(peripheral_config);
+        let timer = device_init.timers(peripheral_config);
+        let pwm = device_init.pwms(peripheral_config);
+        let serial = device_init.serials(peripheral_config);
+        init_and_return(
+            device_init.init_block,
+            item_struct,
+            ComponentGroups {
+                inputs: fields(&inputs),
+                outputs: fields(&outputs),
+                timers: fields(&timer),
+                pwm: fields(&pwm),
+                serial: fields(&serial),
+            },
+        )
+    }
+}
+
+/// The configured clocks, so that code using the constants of the board
+/// compiles on the host
+pub(crate) fn clock_consts(peripheral_config: &HostPeripherals) -> Vec<syn::ImplItem> {
+    let clocks = &peripheral_config.clocks;
+    let configured: Vec<_> = [
+        ("HSE", clocks.hse),
+        ("SYSCLK", clocks.sysclk),
+        ("HCLK", clocks.hclk),
+        ("PCLK1", clocks.pclk1),
+        ("PCLK2", clocks.pclk2),
+        ("ADCCLK", clocks.adcclk),
+    ]
+    .iter()
+    .filter_map(|(name, frequency)| frequency.map(|frequency| (*name, frequency)))
+    .collect();
+    frequency_consts(&configured)
+}
+
+#[test]
+fn init_block_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: host
+clock: 72mhz
+gpio:
+  input:
+    - button:
+      mode: pull_up
+  output:
+    - led: push_pull
+      name: status
+pwm:
+  - fan:
+    pins:
+      - fan_a
+      - fan_b
+    duty: 25%
+serial:
+  - uart0:
+    tx: tx
+    rx: rx
+    baud: 115200
+timer:
+  - delay
+",
+    )
+    .unwrap();
+    let config = crate::device::DeviceConfig::from_yaml(&yaml[0]).unwrap();
+    let item_struct: ItemStruct = parse_quote!(
+        struct Board {}
+    );
+    let (init_block, return_type, items) = config.get_init_fn(&item_struct);
+    assert_eq!(
+        quote::quote!(#return_type).to_string(),
+        quote::quote!(BoardComponents).to_string()
+    );
+    let init_block = quote::quote!(#(#init_block)*).to_string();
+    for expected in [
+        quote::quote!(let button = embedded_hal_mock::eh0::digital::Mock::new(&[]);),
+        quote::quote!(let status = embedded_hal_mock::eh0::digital::Mock::new(&[]);),
+        quote::quote!(let delay = embedded_hal_mock::eh0::delay::NoopDelay::new();),
+        quote::quote!(let uart0 = embedded_hal_mock::eh0::serial::Mock::<u8>::new(&[]);),
+    ] {
+        assert!(
+            init_block.contains(&expected.to_string()),
+            "{} in {}",
+            expected,
+            init_block
+        );
+    }
+    let pwm = &items[3];
+    let expected: Item = parse_quote!(
+        struct BoardPwm {
+            pub fan: [embedded_hal_mock::eh0::digital::Mock; 2usize],
+        }
+    );
+    assert_eq!(
+        quote::quote!(#pwm).to_string(),
+        quote::quote!(#expected).to_string()
+    );
+    let consts = config.get_clock_consts();
+    assert_eq!(
+        quote::quote!(#(#consts)*).to_string(),
+        quote::quote!(
+            const SYSCLK_HZ: u32 = 72000000u32;
+        )
+        .to_string()
+    );
+    assert!(config.get_interrupt_unmasks().is_empty());
+}
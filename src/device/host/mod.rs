@@ -0,0 +1,262 @@
+//! The host, a device without hardware for testing on the development machine.
+//! All components are the embedded-hal 0.2 mocks of the `embedded-hal-mock`
+//! crate (its `eh0` module), so code that uses the components of the
+//! generated init function can be unit tested without a board. The gpios
+//! are free names instead of pins of a chip and the host has neither
+//! interrupts nor clock constraints. The settings of the chips (e.g. modes,
+//! interrupts and baud rates) are parsed like on the board and then
+//! ignored, so the yaml of the board can be reused.
+//! The mocks start without expectations, tests add them with
+//! `update_expectations` before the component is used and check them with
+//! `done`. The init function returns new components on every call.
+//!
+//! The backend is also the smallest template for new devices.
+use yaml_rust::Yaml;
+
+mod generation;
+
+use self::generation::DeviceInit;
+
+use super::{
+    check_components, check_unique, component_key, name_from_yaml, parse_list, pwm_pins_from_yaml,
+    Clocks, Component, Device, Duty, GpioPin, Hertz, PeripheralId,
+};
+use crate::error::{describe, Error, Location};
+
+#[cfg(test)]
+use super::messages;
+
+type Gpio = super::Gpio<String>;
+type Gpios = super::Gpios<String>;
+type Serial = super::Serial<String, String>;
+type Timer = super::Timer<String>;
+
+/// Bindings of the generated init function that are not components
+const RESERVED_BINDINGS: &[&str] = &[];
+
+/// The priorities of the chips are accepted up to the full 8 bits
+const PRIORITY_BITS: u8 = 8;
+
+#[derive(Debug)]
+pub struct HostPeripherals {
+    gpio: Gpios,
+    timer: Vec<Timer>,
+    pwm: Vec<PWM>,
+    serial: Vec<Serial>,
+    /// The clocks are only provided as constants
+    clocks: Clocks,
+}
+
+impl Device for HostPeripherals {
+    fn kinds() -> &'static [&'static str] {
+        &["host"]
+    }
+    /// Parses the peripherals and checks that the names are used once.
+    /// The names are only checked if all components could be parsed.
+    fn from_yaml(yaml: &Yaml, clocks: Clocks) -> Result<Self, Vec<Error>> {
+        let mut errors = Vec::new();
+        let peripherals = Self {
+            gpio: Gpios::from_yaml(&yaml["gpio"], PRIORITY_BITS, &mut errors),
+            timer: parse_list(&yaml["timer"], Timer::from_yaml, &mut errors),
+            pwm: parse_list(&yaml["pwm"], PWM::from_yaml, &mut errors),
+            serial: parse_list(
+                &yaml["serial"],
+                |serial| Serial::from_yaml(serial, PRIORITY_BITS),
+                &mut errors,
+            ),
+            clocks,
+        };
+        if errors.is_empty() {
+            errors = peripherals.check();
+        }
+        if errors.is_empty() {
+            Ok(peripherals)
+        } else {
+            Err(errors)
+        }
+    }
+    fn init_fn(
+        &self,
+        item_struct: &syn::ItemStruct,
+    ) -> (Vec<syn::Stmt>, syn::Type, Vec<syn::Item>) {
+        DeviceInit::get_init_block(self, item_struct)
+    }
+    fn clock_consts(&self) -> Vec<syn::ImplItem> {
+        generation::clock_consts(self)
+    }
+    /// The host has no interrupt controller
+    fn interrupt_unmasks(&self) -> Vec<syn::Stmt> {
+        Vec::new()
+    }
+}
+
+impl HostPeripherals {
+    /// Checks that every gpio, timer, pwm and serial is used once
+    fn check(&self) -> Vec<Error> {
+        let mut errors = check_unique(
+            self.timer
+                .iter()
+                .map(|timer| (&timer.id, format!("Timer '{}'", timer.id), timer.location)),
+        );
+        errors.append(&mut check_unique(
+            self.pwm
+                .iter()
+                .map(|pwm| (&pwm.id, format!("Pwm '{}'", pwm.id), pwm.location)),
+        ));
+        errors.append(&mut check_unique(self.serial.iter().map(|serial| {
+            (
+                &serial.id,
+                format!("Serial '{}'", serial.id),
+                serial.location,
+            )
+        })));
+        errors.append(&mut check_components(RESERVED_BINDINGS, &self.components()));
+        errors
+    }
+    /// The components in the order of the yaml sections
+    fn components(&self) -> [&dyn Component<String>; 4] {
+        [&self.gpio, &self.timer, &self.pwm, &self.serial]
+    }
+}
+
+/// The host has no pins, any name of a gpio is accepted. The name is also
+/// the binding and has to be an identifier.
+impl GpioPin for String {
+    const EXAMPLE: &'static str = "led";
+    const CHOSEN_NAME: bool = true;
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(name.to_string())
+    }
+    fn name(&self) -> String {
+        self.clone()
+    }
+}
+
+/// The timers and serials are named in the yaml as well
+impl PeripheralId for String {
+    const EXAMPLE: &'static str = "uart0";
+    const CHOSEN_NAME: bool = true;
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        yaml.as_str()
+            .map(str::to_string)
+            .ok_or_else(|| Error::at(yaml, format!("Unable to parse name {}", describe(yaml))))
+    }
+    fn lower(&self) -> &str {
+        self
+    }
+}
+
+impl Component<String> for Serial {
+    fn gpios(&self) -> Vec<(String, Location)> {
+        vec![
+            (self.tx.clone(), self.location),
+            (self.rx.clone(), self.location),
+        ]
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        self.whole_bindings()
+    }
+}
+
+/// A pwm with one mocked channel per pin. The initial state is only
+/// parsed, setting it would be an unexpected transaction on the mocks.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
+pub struct PWM {
+    id: String,
+    /// Binding and field name chosen in the yaml
+    name: Option<String>,
+    pins: Vec<String>,
+    location: Location,
+}
+
+impl PWM {
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        let id = String::from_yaml(component_key(yaml, "pwm", "pwm0")?)?;
+        let pins = pwm_pins_from_yaml(yaml, false)?
+            .into_iter()
+            .map(|(pin, _)| pin)
+            .collect();
+        Hertz::from_yaml(&yaml["freq"])?;
+        Duty::from_yaml(&yaml["duty"])?;
+        Ok(Self {
+            id,
+            name: name_from_yaml(&yaml["name"])?,
+            pins,
+            location: Location::of(yaml),
+        })
+    }
+    /// The name of the generated binding
+    fn binding(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.id.clone())
+    }
+}
+
+impl Component<String> for PWM {
+    fn gpios(&self) -> Vec<(String, Location)> {
+        self.pins
+            .iter()
+            .map(|pin| (pin.clone(), self.location))
+            .collect()
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        vec![(
+            self.binding(),
+            format!("pwm '{}'", self.id),
+            true,
+            self.location,
+        )]
+    }
+}
+
+#[test]
+fn check_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: host
+gpio:
+  input:
+    - button:
+      mode: pull_up
+      interrupt: falling
+      priority: 1
+serial:
+  - uart0:
+    tx: tx
+    rx: rx
+    baud: 115200
+    interrupt: rx
+",
+    )
+    .unwrap();
+    // the interrupts of the board are ignored
+    let clocks = Clocks::from_yaml(&yaml[0]).unwrap();
+    assert!(HostPeripherals::from_yaml(&yaml[0], clocks).is_ok());
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: host
+gpio:
+  output:
+    - led: push_pull
+    - 2led: push_pull
+pwm:
+  - fan:
+    pins:
+      - led
+serial:
+  - uart0:
+    tx: tx
+    rx: rx
+    baud: 115200
+timer:
+  - delay
+  - delay
+",
+    )
+    .unwrap();
+    let clocks = Clocks::from_yaml(&yaml[0]).unwrap();
+    let message = messages(HostPeripherals::from_yaml(&yaml[0], clocks).unwrap_err());
+    assert!(message.contains("Timer 'delay' is used multiple times"));
+    assert!(message.contains("Gpio 'led' is used multiple times"));
+    assert!(message.contains("Name '2led' of gpio '2led' is not a valid identifier"));
+}
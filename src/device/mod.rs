@@ -1,82 +1,1273 @@
-use core::panic;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+};
 
 use yaml_rust::Yaml;
 
+use host::HostPeripherals;
+use nrf52840::Nrf52840Peripherals;
+use rp2040::Rp2040Peripherals;
 use stm32f1xx::Stm32f1xxPeripherals;
+use stm32f4xx::Stm32f4xxPeripherals;
 
-use self::stm32f1xx::init_stmts_and_return_tys;
+use crate::error::{describe, Error, Location};
 
+mod generation;
+mod host;
+mod nrf52840;
+mod rp2040;
+mod stm32;
 mod stm32f1xx;
+mod stm32f4xx;
 
 #[derive(Debug)]
 pub(crate) struct DeviceConfig {
-    kind: DeviceKind,
-    clock: Hertz,
+    device: Box<dyn Device>,
 }
 
-#[non_exhaustive]
-#[derive(Debug)]
-enum DeviceKind {
-    Stm32f1xx(Stm32f1xxPeripherals),
+/// The backend of a chip family.
+/// A backend parses the peripherals of its devices from the yaml and
+/// generates the init code for them. New backends are registered in
+/// [`backends`].
+trait Device: Debug {
+    /// The values of the 'kind' key that select the backend
+    fn kinds() -> &'static [&'static str]
+    where
+        Self: Sized;
+    /// Parses and checks the peripherals, the clocks are already parsed
+    /// from the common 'clock' or 'clocks' section
+    fn from_yaml(yaml: &Yaml, clocks: Clocks) -> Result<Self, Vec<Error>>
+    where
+        Self: Sized;
+    /// Returns the statements and the return type of the init function
+    /// and the definitions of the returned structs
+    fn init_fn(&self, item_struct: &syn::ItemStruct)
+        -> (Vec<syn::Stmt>, syn::Type, Vec<syn::Item>);
+    /// Associated constants with the frequencies of the configured clocks
+    fn clock_consts(&self) -> Vec<syn::ImplItem>;
+    /// The unsafe statements that unmask the configured interrupts
+    fn interrupt_unmasks(&self) -> Vec<syn::Stmt>;
+}
+
+type DeviceParser = fn(&Yaml, Clocks) -> Result<Box<dyn Device>, Vec<Error>>;
+
+/// A type erased [`Device`] implementation
+struct Backend {
+    kinds: &'static [&'static str],
+    from_yaml: DeviceParser,
+}
+
+impl Backend {
+    fn of<D: Device + 'static>() -> Self {
+        Self {
+            kinds: D::kinds(),
+            from_yaml: |yaml, clocks| {
+                D::from_yaml(yaml, clocks).map(|device| Box::new(device) as Box<dyn Device>)
+            },
+        }
+    }
+}
+
+/// All supported chip families
+fn backends() -> Vec<Backend> {
+    vec![
+        Backend::of::<Stm32f1xxPeripherals>(),
+        Backend::of::<Stm32f4xxPeripherals>(),
+        Backend::of::<Nrf52840Peripherals>(),
+        Backend::of::<Rp2040Peripherals>(),
+        Backend::of::<HostPeripherals>(),
+    ]
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Baud(pub u32);
 impl Baud {
-    pub fn from_str(str: &str) -> Self {
-        Self(str.parse::<u32>().expect("Unable to parse baud rate"))
+    pub fn from_str(str: &str) -> Result<Self, String> {
+        match str.parse::<i64>() {
+            Ok(int) => Self::from_i64(int),
+            Err(_) => Err(format!("Unable to parse baud rate '{}'", str)),
+        }
     }
-    pub fn from_i64(int: i64) -> Self {
-        Self(int as u32)
+    pub fn from_i64(int: i64) -> Result<Self, String> {
+        if int <= 0 || int > u32::MAX as i64 {
+            return Err(format!("Baud rate {} is out of range", int));
+        }
+        Ok(Self(int as u32))
+    }
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml {
+            Yaml::Integer(baud) => Self::from_i64(*baud),
+            Yaml::String(baud) => Self::from_str(baud),
+            other => Err(format!("Unable to parse baud rate {}", describe(other))),
+        }
+        .map_err(|message| Error::at(yaml, message))
     }
 }
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 struct Hertz(usize);
 
 impl Hertz {
-    pub fn from_str(str: &str) -> Self {
-        let mut last_digit = 0;
-        for char in str.chars() {
-            if char.is_ascii_digit() {
-                last_digit += 1;
-            } else {
-                break;
-            }
-        }
+    pub fn from_str(str: &str) -> Result<Self, String> {
+        let last_digit = str
+            .find(|char: char| !char.is_ascii_digit())
+            .unwrap_or(str.len());
         let (amount, unit) = str.split_at(last_digit);
-        let factor = match unit.to_lowercase().as_str() {
+        let factor = match unit.trim().to_lowercase().as_str() {
             "hz" => 1,
             "khz" => 1_000,
             "mhz" => 1_000_000,
             "ghz" => 1_000_000_000,
-            _ => panic!("unknown frequency unit (unit is 'hz', 'khz', 'mhz' or 'ghz)"),
+            _ => {
+                return Err(format!(
+                    "Unknown frequency unit in '{}' (unit is 'hz', 'khz', 'mhz' or 'ghz')",
+                    str
+                ))
+            }
         };
-        Self(amount.parse::<usize>().expect("Unable to parse frequency") * factor)
+        amount
+            .parse::<usize>()
+            .ok()
+            .and_then(|amount| amount.checked_mul(factor))
+            .map(Self)
+            .ok_or_else(|| format!("Unable to parse frequency '{}'", str))
+    }
+    /// An optional frequency like '8mhz'
+    pub fn from_yaml(yaml: &Yaml) -> Result<Option<Self>, Error> {
+        match yaml {
+            Yaml::String(str) => Self::from_str(str)
+                .map(Some)
+                .map_err(|message| Error::at(yaml, message)),
+            Yaml::BadValue => Ok(None),
+            other => Err(Error::at(
+                yaml,
+                format!(
+                    "Unable to parse frequency {} (e.g. '8mhz')",
+                    describe(other)
+                ),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Hertz {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            0 => write!(f, "0hz"),
+            hz if hz % 1_000_000 == 0 => write!(f, "{}mhz", hz / 1_000_000),
+            hz if hz % 1_000 == 0 => write!(f, "{}khz", hz / 1_000),
+            hz => write!(f, "{}hz", hz),
+        }
+    }
+}
+
+/// The requested frequencies of the clock tree.
+/// Unset frequencies are left to the hal, which derives them from the
+/// others or keeps the reset configuration.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct Clocks {
+    /// Frequency of an external crystal, the internal oscillator is used if unset
+    hse: Option<Hertz>,
+    sysclk: Option<Hertz>,
+    hclk: Option<Hertz>,
+    pclk1: Option<Hertz>,
+    pclk2: Option<Hertz>,
+    adcclk: Option<Hertz>,
+}
+
+impl Clocks {
+    /// Reads the 'clocks' section or, for older configurations,
+    /// the system clock from the plain 'clock' key.
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self, Vec<Error>> {
+        match (&yaml["clocks"], &yaml["clock"]) {
+            (Yaml::BadValue, Yaml::BadValue) => Ok(Self::default()),
+            (Yaml::BadValue, clock) => Ok(Self {
+                sysclk: Self::frequency_from_yaml(clock, "sysclk").map_err(|error| vec![error])?,
+                ..Self::default()
+            }),
+            (clocks, Yaml::BadValue) => {
+                let config = clocks
+                    .as_hash()
+                    .ok_or_else(|| vec![Error::at(clocks, "Expected a map of clocks")])?;
+                let mut errors = Vec::new();
+                for key in config.keys() {
+                    match key.as_str() {
+                        Some("hse") | Some("sysclk") | Some("hclk") | Some("pclk1")
+                        | Some("pclk2") | Some("adcclk") => {}
+                        _ => errors.push(Error::at(
+                            key,
+                            format!(
+                                "Unknown clock {} (hse, sysclk, hclk, pclk1, pclk2 or adcclk)",
+                                describe(key)
+                            ),
+                        )),
+                    }
+                }
+                let mut frequency = |key: &str| {
+                    Self::frequency_from_yaml(&clocks[key], key).unwrap_or_else(|error| {
+                        errors.push(error);
+                        None
+                    })
+                };
+                let clocks = Self {
+                    hse: frequency("hse"),
+                    sysclk: frequency("sysclk"),
+                    hclk: frequency("hclk"),
+                    pclk1: frequency("pclk1"),
+                    pclk2: frequency("pclk2"),
+                    adcclk: frequency("adcclk"),
+                };
+                if errors.is_empty() {
+                    Ok(clocks)
+                } else {
+                    Err(errors)
+                }
+            }
+            (_, clock) => Err(vec![Error::at(
+                clock,
+                "'clock' and 'clocks' cannot be used together",
+            )]),
+        }
+    }
+    /// The backends divide by the requested frequencies, so a clock cannot be 0hz
+    fn frequency_from_yaml(yaml: &Yaml, clock: &str) -> Result<Option<Hertz>, Error> {
+        match Hertz::from_yaml(yaml)? {
+            Some(Hertz(0)) => Err(Error::at(yaml, format!("Clock '{}' cannot be 0hz", clock))),
+            frequency => Ok(frequency),
+        }
+    }
+}
+
+/// Fractions are stored in parts of [`FRACTION_SCALE`] so that they
+/// can be applied with integer arithmetic.
+const FRACTION_SCALE: u32 = 10_000;
+
+/// Accepts percentages ('75%') and fractions ('0.75') between 0 and 1
+fn parse_fraction(str: &str, name: &str) -> Result<u32, String> {
+    let str = str.trim();
+    let fraction = match str.strip_suffix('%') {
+        Some(percent) => {
+            percent
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Unable to parse {} percentage '{}'", name, str))?
+                / 100.0
+        }
+        None => str
+            .parse::<f64>()
+            .map_err(|_| format!("Unable to parse {} fraction '{}'", name, str))?,
+    };
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(format!("{} '{}' is not between 0% and 100%", name, str));
+    }
+    Ok((fraction * FRACTION_SCALE as f64).round() as u32)
+}
+
+/// Reads a fraction from a string ('75%'), a real ('0.75') or an integer ('1')
+fn fraction_from_yaml(yaml: &Yaml, name: &str) -> Result<Option<u32>, Error> {
+    match yaml {
+        Yaml::String(str) | Yaml::Real(str) => parse_fraction(str, name).map(Some),
+        Yaml::Integer(int) => parse_fraction(&int.to_string(), name).map(Some),
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        other => Err(format!("Unable to parse {} {}", name, describe(other))),
+    }
+    .map_err(|message| Error::at(yaml, message))
+}
+
+/// A duty cycle in parts of [`Duty::SCALE`] so that it can be applied
+/// to the maximum duty of a pwm channel with integer arithmetic.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+struct Duty(u32);
+
+impl Duty {
+    const SCALE: u32 = FRACTION_SCALE;
+    pub fn from_yaml(yaml: &Yaml) -> Result<Option<Self>, Error> {
+        Ok(fraction_from_yaml(yaml, "duty")?.map(Self))
+    }
+}
+
+/// The accepted relative deviation of a generated frequency (e.g. a baud rate)
+/// from the requested one, in parts of [`Tolerance::SCALE`].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+struct Tolerance(u32);
+
+impl Tolerance {
+    const SCALE: u32 = FRACTION_SCALE;
+    /// 2% are commonly tolerated by serial receivers
+    const DEFAULT: Tolerance = Tolerance(200);
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        Ok(fraction_from_yaml(yaml, "tolerance")?
+            .map(Self)
+            .unwrap_or(Self::DEFAULT))
+    }
+    /// The relative deviation of 'actual' from 'expected'
+    fn deviation(expected: u64, actual: u64) -> Self {
+        Self((actual.abs_diff(expected) * Self::SCALE as u64 / expected) as u32)
+    }
+}
+
+impl std::fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}%", self.0 as f64 * 100.0 / Self::SCALE as f64)
+    }
+}
+
+/// Parses the components of an optional list and collects the errors of
+/// all components that cannot be parsed
+fn parse_list<T>(
+    yaml: &Yaml,
+    parse: impl Fn(&Yaml) -> Result<T, Error>,
+    errors: &mut Vec<Error>,
+) -> Vec<T> {
+    match yaml {
+        Yaml::Array(components) => components
+            .iter()
+            .filter_map(|component| parse(component).map_err(|e| errors.push(e)).ok())
+            .collect(),
+        Yaml::BadValue | Yaml::Null => Vec::new(),
+        other => {
+            errors.push(Error::at(
+                other,
+                format!("Expected a list but found {}", describe(other)),
+            ));
+            Vec::new()
+        }
+    }
+}
+
+/// Components are maps that start with the component as a key
+/// without a value (e.g. `- pa0:` or `- usart1:`)
+fn component_key<'a>(yaml: &'a Yaml, component: &str, example: &str) -> Result<&'a Yaml, Error> {
+    let hash = yaml.as_hash().ok_or_else(|| {
+        Error::at(
+            yaml,
+            format!("Unexpected {} format, found {}", component, describe(yaml)),
+        )
+    })?;
+    let mut keys = hash
+        .iter()
+        .filter(|(_, value)| value.is_null())
+        .map(|(key, _)| key);
+    match (keys.next(), keys.next()) {
+        (Some(key), None) => Ok(key),
+        (None, _) => Err(Error::at(
+            yaml,
+            format!("Missing the {} (e.g. '- {}:')", component, example),
+        )),
+        (Some(_), Some(key)) => Err(Error::at(key, format!("Expected a single {}", component))),
+    }
+}
+
+/// A key that has to be present in the component
+fn required<'a>(yaml: &'a Yaml, key: &str, component: &str) -> Result<&'a Yaml, Error> {
+    match &yaml[key] {
+        Yaml::BadValue => Err(Error::at(
+            yaml,
+            format!("Missing key '{}' in {}", key, component),
+        )),
+        value => Ok(value),
+    }
+}
+
+/// Looks up a keyword (e.g. `mode: pull_up`) in the options for 'what'
+fn keyword_from_yaml<T: Copy>(yaml: &Yaml, what: &str, options: &[(&str, T)]) -> Result<T, Error> {
+    options
+        .iter()
+        .find(|(keyword, _)| yaml.as_str() == Some(keyword))
+        .map(|(_, value)| *value)
+        .ok_or_else(|| {
+            let keywords: Vec<&str> = options.iter().map(|(keyword, _)| *keyword).collect();
+            let (last, others) = keywords.split_last().expect("keywords without options");
+            let keywords = match others {
+                [] => last.to_string(),
+                others => format!("{} or {}", others.join(", "), last),
+            };
+            Error::at(
+                yaml,
+                format!("Unable to parse {} {} ({})", what, describe(yaml), keywords),
+            )
+        })
+}
+
+/// The node of the clock section, errors of the solved clock tree are reported there
+fn clocks_yaml(yaml: &Yaml) -> &Yaml {
+    match &yaml["clocks"] {
+        Yaml::BadValue => &yaml["clock"],
+        clocks => clocks,
+    }
+}
+
+/// Parses an optional name for a binding, it is validated in [`check_bindings`]
+fn name_from_yaml(yaml: &Yaml) -> Result<Option<String>, Error> {
+    match yaml {
+        Yaml::String(name) => Ok(Some(name.clone())),
+        Yaml::BadValue => Ok(None),
+        other => Err(Error::at(
+            other,
+            format!("Unable to parse name {}", describe(other)),
+        )),
+    }
+}
+
+/// Checks the bindings of the generated init function, given with a
+/// description of their origin, whether the name was chosen in the yaml and
+/// the component that declares them. Names from the yaml have to be unique
+/// identifiers, collisions between generated names are reported by the
+/// backends as multiple uses of a component.
+fn check_bindings(
+    reserved: &[&str],
+    component_bindings: Vec<(String, String, bool, Location)>,
+) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let mut bindings: BTreeMap<String, (String, bool)> = reserved
+        .iter()
+        .map(|binding| (binding.to_string(), ("the init code".to_string(), false)))
+        .collect();
+    for (binding, origin, named, location) in component_bindings {
+        if named && syn::parse_str::<syn::Ident>(&binding).is_err() {
+            errors.push(Error::new(
+                location,
+                format!("Name '{}' of {} is not a valid identifier", binding, origin),
+            ));
+            continue;
+        }
+        match bindings.get(&binding) {
+            Some((other_origin, other_named)) if named || *other_named => errors.push(Error::new(
+                location,
+                format!(
+                    "Name '{}' of {} is already used by {}",
+                    binding, origin, other_origin
+                ),
+            )),
+            Some(_) => {}
+            None => {
+                bindings.insert(binding, (origin, named));
+            }
+        }
+    }
+    errors
+}
+
+/// The priority of an interrupt in the NVIC, lower values are more urgent.
+/// The chips implement only the upper bits of the 8 bit priority registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Priority(u8);
+
+impl Priority {
+    fn from_yaml(yaml: &Yaml, implemented_bits: u8) -> Result<Option<Self>, Error> {
+        let max = (1 << implemented_bits) - 1;
+        match yaml {
+            Yaml::Integer(priority) if (0..=max).contains(priority) => {
+                Ok(Some(Self(*priority as u8)))
+            }
+            Yaml::BadValue => Ok(None),
+            other => Err(Error::at(
+                other,
+                format!(
+                    "Unable to parse priority {} (the {} implemented priority bits allow 0 to {})",
+                    describe(other),
+                    implemented_bits,
+                    max
+                ),
+            )),
+        }
+    }
+    /// The value for the priority register, the unimplemented bits are the lower ones
+    fn register_value(&self, implemented_bits: u8) -> u8 {
+        self.0 << (8 - implemented_bits)
+    }
+}
+
+/// Maps the nvic interrupts of the given sources to their priority.
+/// A source is given by its interrupt, its name, its priority and its
+/// component. Sources that share an interrupt have to agree on the priority,
+/// a source without a priority does not inherit the one of the others.
+fn merge_interrupts(
+    sources: Vec<(String, String, Option<Priority>, Location)>,
+) -> Result<BTreeMap<String, Option<Priority>>, Vec<Error>> {
+    let mut errors = Vec::new();
+    let mut interrupts: BTreeMap<String, (String, Option<Priority>)> = BTreeMap::new();
+    for (interrupt, source, priority, location) in sources {
+        match interrupts.get_mut(&interrupt) {
+            Some((other, other_priority)) if *other_priority != priority => {
+                let describe = |priority: Option<Priority>| match priority {
+                    Some(priority) => priority.0.to_string(),
+                    None => "unset".to_string(),
+                };
+                errors.push(Error::new(
+                    location,
+                    format!(
+                        "'{}' and '{}' share the interrupt {} but have different priorities ({} and {})",
+                        other,
+                        source,
+                        interrupt,
+                        describe(*other_priority),
+                        describe(priority)
+                    ),
+                ))
+            }
+            Some(_) => {}
+            None => {
+                interrupts.insert(interrupt, (source, priority));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(interrupts
+            .into_iter()
+            .map(|(interrupt, (_, priority))| (interrupt, priority))
+            .collect())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The frequency of pwms without a 'freq' key, the hals need one to
+/// configure the pwm even if the yaml does not specify an initial state
+const DEFAULT_PWM_FREQUENCY: Hertz = Hertz(1_000);
+
+/// A gpio in the naming of a device, the backends implement it for their pin type
+trait GpioPin: Clone + Ord + Debug {
+    /// A gpio for the error messages (e.g. 'pa0')
+    const EXAMPLE: &'static str;
+    /// Whether the name of the gpio is chosen in the yaml instead of the datasheet.
+    /// Its default binding is then checked like a name given with the 'name' key.
+    const CHOSEN_NAME: bool = false;
+    fn parse(name: &str) -> Result<Self, String>;
+    /// The name of the gpio in the messages
+    fn name(&self) -> String;
+    /// The name of the generated binding if no other name is given
+    fn binding(&self) -> String {
+        self.name()
+    }
+    /// Checks that the parsed gpio exists on the device
+    fn check_exists(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn pin_from_yaml<P: GpioPin>(yaml: &Yaml) -> Result<P, Error> {
+    yaml.as_str()
+        .ok_or_else(|| {
+            format!(
+                "Unable to parse gpio {} (e.g. '{}')",
+                describe(yaml),
+                P::EXAMPLE
+            )
+        })
+        .and_then(P::parse)
+        .map_err(|e| Error::at(yaml, e))
+}
+
+/// A peripheral instance that a component claims by its name (e.g. 'usart1')
+trait PeripheralId: Clone + Ord + Debug {
+    /// A name for the error messages
+    const EXAMPLE: &'static str;
+    /// Whether the name is chosen in the yaml, see [`GpioPin::CHOSEN_NAME`]
+    const CHOSEN_NAME: bool = false;
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error>;
+    /// The name in the yaml, which is also the default binding
+    fn lower(&self) -> &str;
+}
+
+/// A component of the yaml. The checks that all devices share run over the
+/// components in the order of the yaml sections.
+trait Component<P> {
+    /// The gpios the component takes
+    fn gpios(&self) -> Vec<(P, Location)>;
+    /// The bindings of the generated init function, see [`check_bindings`]
+    fn bindings(&self) -> Vec<(String, String, bool, Location)>;
+}
+
+impl<P, C: Component<P>> Component<P> for Vec<C> {
+    fn gpios(&self) -> Vec<(P, Location)> {
+        self.iter().flat_map(Component::gpios).collect()
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        self.iter().flat_map(Component::bindings).collect()
     }
 }
 
+/// Reports the components that claim a peripheral or gpio that an earlier
+/// component already claimed. A claim is given by what is claimed, its
+/// description (e.g. "Serial 'usart1'") and the claiming component.
+fn check_unique<K: Ord>(claims: impl IntoIterator<Item = (K, String, Location)>) -> Vec<Error> {
+    let mut claimed = BTreeSet::new();
+    claims
+        .into_iter()
+        .filter_map(|(key, description, location)| {
+            (!claimed.insert(key))
+                .then(|| Error::new(location, format!("{} is used multiple times", description)))
+        })
+        .collect()
+}
+
+/// Checks that the gpios of the components exist and are used once and
+/// that the bindings are valid, see [`check_bindings`]
+fn check_components<P: GpioPin>(reserved: &[&str], components: &[&dyn Component<P>]) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let mut existing = Vec::new();
+    for (pin, location) in components.iter().flat_map(|component| component.gpios()) {
+        match pin.check_exists() {
+            Ok(()) => existing.push((pin.clone(), format!("Gpio '{}'", pin.name()), location)),
+            Err(err) => errors.push(Error::new(location, err)),
+        }
+    }
+    errors.append(&mut check_unique(existing));
+    let bindings = components
+        .iter()
+        .flat_map(|component| component.bindings())
+        .collect();
+    errors.append(&mut check_bindings(reserved, bindings));
+    errors
+}
+
+/// The gpios of all components, each gpio is split from its port once
+fn used_gpios<P>(components: &[&dyn Component<P>]) -> Vec<P> {
+    components
+        .iter()
+        .flat_map(|component| component.gpios())
+        .map(|(pin, _)| pin)
+        .collect()
+}
+
+/// The modes of the gpios in the yaml, the backends map them to their hal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PinMode {
+    InputFloating,
+    InputPullUp,
+    InputPullDown,
+    OutputPushPull,
+    OutputOpenDrain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InterruptMode {
+    None,
+    Rising,
+    Falling,
+    RisingFalling,
+}
+
+#[derive(Debug)]
+struct Gpios<P> {
+    input: Vec<Gpio<P>>,
+    output: Vec<Gpio<P>>,
+}
+
+impl<P: GpioPin> Gpios<P> {
+    /// Parses the 'input' and 'output' lists of the gpio section
+    fn from_yaml(yaml: &Yaml, priority_bits: u8, errors: &mut Vec<Error>) -> Self {
+        Self {
+            input: parse_list(
+                &yaml["input"],
+                |gpio| Gpio::input_from_yaml(gpio, priority_bits),
+                errors,
+            ),
+            output: parse_list(&yaml["output"], Gpio::output_from_yaml, errors),
+        }
+    }
+}
+
+impl<P: GpioPin> Component<P> for Gpios<P> {
+    fn gpios(&self) -> Vec<(P, Location)> {
+        let mut gpios = self.input.gpios();
+        gpios.append(&mut self.output.gpios());
+        gpios
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        let mut bindings = self.input.bindings();
+        bindings.append(&mut self.output.bindings());
+        bindings
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Gpio<P> {
+    pin: P,
+    /// Binding and field name chosen in the yaml
+    name: Option<String>,
+    mode: PinMode,
+    interrupt_mode: InterruptMode,
+    priority: Option<Priority>,
+    location: Location,
+}
+
+impl<P: GpioPin> Gpio<P> {
+    fn input_from_yaml(gpio_yaml: &Yaml, priority_bits: u8) -> Result<Self, Error> {
+        let pin: P = pin_from_yaml(component_key(gpio_yaml, "input gpio", P::EXAMPLE)?)?;
+        let mode = keyword_from_yaml(
+            required(gpio_yaml, "mode", "input gpio")?,
+            "mode",
+            &[
+                ("pull_up", PinMode::InputPullUp),
+                ("pull_down", PinMode::InputPullDown),
+                ("floating", PinMode::InputFloating),
+            ],
+        )?;
+        let interrupt_mode = match &gpio_yaml["interrupt"] {
+            Yaml::BadValue => InterruptMode::None,
+            interrupt => keyword_from_yaml(
+                interrupt,
+                "interrupt mode",
+                &[
+                    ("rising", InterruptMode::Rising),
+                    ("falling", InterruptMode::Falling),
+                    ("rising_falling", InterruptMode::RisingFalling),
+                    ("none", InterruptMode::None),
+                ],
+            )?,
+        };
+        let priority = Priority::from_yaml(&gpio_yaml["priority"], priority_bits)?;
+        if priority.is_some() && interrupt_mode == InterruptMode::None {
+            return Err(Error::at(
+                &gpio_yaml["priority"],
+                format!(
+                    "Gpio '{}' has a priority but does not interrupt",
+                    pin.name()
+                ),
+            ));
+        }
+        Ok(Gpio {
+            pin,
+            name: name_from_yaml(&gpio_yaml["name"])?,
+            mode,
+            interrupt_mode,
+            priority,
+            location: Location::of(gpio_yaml),
+        })
+    }
+    /// Outputs are given as the gpio with its mode (e.g. `- pb5: push_pull`)
+    fn output_from_yaml(gpio_yaml: &Yaml) -> Result<Self, Error> {
+        let config = gpio_yaml.as_hash().ok_or_else(|| {
+            Error::at(
+                gpio_yaml,
+                format!(
+                    "Unexpected output gpio format, found {}",
+                    describe(gpio_yaml)
+                ),
+            )
+        })?;
+        let mut gpio = None;
+        for (key, value) in config {
+            match key.as_str() {
+                Some("name") => {}
+                _ if gpio.is_none() => gpio = Some((key, value)),
+                _ => {
+                    return Err(Error::at(
+                        key,
+                        format!(
+                        "Expected a single mode element for output gpio key (e.g. {}: push_pull)",
+                        P::EXAMPLE
+                    ),
+                    ))
+                }
+            }
+        }
+        let (pin_yaml, mode_yaml) = gpio.ok_or_else(|| {
+            Error::at(
+                gpio_yaml,
+                format!("Missing the output gpio (e.g. {}: push_pull)", P::EXAMPLE),
+            )
+        })?;
+        Ok(Gpio {
+            pin: pin_from_yaml(pin_yaml)?,
+            name: name_from_yaml(&gpio_yaml["name"])?,
+            mode: keyword_from_yaml(
+                mode_yaml,
+                "output mode",
+                &[
+                    ("push_pull", PinMode::OutputPushPull),
+                    ("open_drain", PinMode::OutputOpenDrain),
+                ],
+            )?,
+            interrupt_mode: InterruptMode::None,
+            priority: None,
+            location: Location::of(gpio_yaml),
+        })
+    }
+    /// The name of the generated binding
+    fn binding(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.pin.binding())
+    }
+}
+
+impl<P: GpioPin> Component<P> for Gpio<P> {
+    fn gpios(&self) -> Vec<(P, Location)> {
+        vec![(self.pin.clone(), self.location)]
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        vec![(
+            self.binding(),
+            format!("gpio '{}'", self.pin.name()),
+            self.name.is_some() || P::CHOSEN_NAME,
+            self.location,
+        )]
+    }
+}
+
+#[derive(Debug)]
+struct Timer<I> {
+    id: I,
+    location: Location,
+}
+
+impl<I: PeripheralId> Timer<I> {
+    /// Timers are given by their name (e.g. `- tim2`)
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        Ok(Self {
+            id: I::from_yaml(yaml)?,
+            location: Location::of(yaml),
+        })
+    }
+}
+
+impl<I: PeripheralId, P> Component<P> for Timer<I> {
+    fn gpios(&self) -> Vec<(P, Location)> {
+        Vec::new()
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        vec![(
+            self.id.lower().to_string(),
+            format!("timer '{}'", self.id.lower()),
+            I::CHOSEN_NAME,
+            self.location,
+        )]
+    }
+}
+
+/// Parses the 'pins' list of a pwm. If the channels have their own bindings,
+/// a pin can also be given as key with a name (e.g. `- pa1:` and `name: fan`).
+fn pwm_pins_from_yaml<P: GpioPin>(
+    yaml: &Yaml,
+    named_channels: bool,
+) -> Result<Vec<(P, Option<String>)>, Error> {
+    let pins_yaml = required(yaml, "pins", "pwm")?;
+    let pins_list = pins_yaml.as_vec().ok_or_else(|| {
+        Error::at(
+            pins_yaml,
+            format!(
+                "Expected a list of pwm pins but found {}",
+                describe(pins_yaml)
+            ),
+        )
+    })?;
+    if pins_list.is_empty() {
+        return Err(Error::at(pins_yaml, "A pwm needs at least one pin"));
+    }
+    pins_list
+        .iter()
+        .map(|pin_yaml| match pin_yaml {
+            Yaml::String(_) => Ok((pin_from_yaml(pin_yaml)?, None)),
+            Yaml::Hash(_) if named_channels => Ok((
+                pin_from_yaml(component_key(pin_yaml, "pwm pin", P::EXAMPLE)?)?,
+                name_from_yaml(&pin_yaml["name"])?,
+            )),
+            other => Err(Error::at(
+                other,
+                format!("Unable to parse pwm pin {}", describe(other)),
+            )),
+        })
+        .collect()
+}
+
+/// A serial port with the frame settings of the yaml. The backends check
+/// which settings their hardware supports.
+#[derive(Debug)]
+struct Serial<I, P> {
+    id: I,
+    /// Binding and field name chosen in the yaml
+    name: Option<String>,
+    rx: P,
+    tx: P,
+    baud_rate: Baud,
+    /// Accepted deviation of the generated baud rate from the requested one
+    baud_tolerance: Tolerance,
+    parity: Parity,
+    stop_bits: StopBits,
+    /// Number of data bits in a frame, without the parity bit
+    word_length: u8,
+    interrupt: SerialInterrupt,
+    priority: Option<Priority>,
+    location: Location,
+}
+
+impl<I: PeripheralId, P: GpioPin> Serial<I, P> {
+    fn from_yaml(yaml: &Yaml, priority_bits: u8) -> Result<Self, Error> {
+        let serial = Self {
+            id: I::from_yaml(component_key(yaml, "serial", I::EXAMPLE)?)?,
+            name: name_from_yaml(&yaml["name"])?,
+            rx: pin_from_yaml(required(yaml, "rx", "serial")?)?,
+            tx: pin_from_yaml(required(yaml, "tx", "serial")?)?,
+            baud_rate: Baud::from_yaml(required(yaml, "baud", "serial")?)?,
+            baud_tolerance: Tolerance::from_yaml(&yaml["baud_tolerance"])?,
+            parity: match &yaml["parity"] {
+                Yaml::BadValue => Parity::None,
+                parity => keyword_from_yaml(
+                    parity,
+                    "parity",
+                    &[
+                        ("none", Parity::None),
+                        ("even", Parity::Even),
+                        ("odd", Parity::Odd),
+                    ],
+                )?,
+            },
+            stop_bits: match &yaml["stop_bits"] {
+                Yaml::Integer(1) | Yaml::BadValue => StopBits::Stop1,
+                Yaml::Integer(2) => StopBits::Stop2,
+                Yaml::Real(real) if real == "0.5" => StopBits::Stop0p5,
+                Yaml::Real(real) if real == "1.5" => StopBits::Stop1p5,
+                other => {
+                    return Err(Error::at(
+                        other,
+                        format!(
+                            "Unable to parse stop bits {} (0.5, 1, 1.5 or 2)",
+                            describe(other)
+                        ),
+                    ))
+                }
+            },
+            word_length: match &yaml["word_length"] {
+                Yaml::Integer(bits @ 5..=9) => *bits as u8,
+                Yaml::BadValue => 8,
+                other => {
+                    return Err(Error::at(
+                        other,
+                        format!("Unable to parse word length {} (5 to 9)", describe(other)),
+                    ))
+                }
+            },
+            interrupt: match &yaml["interrupt"] {
+                Yaml::BadValue => SerialInterrupt::None,
+                interrupt => keyword_from_yaml(
+                    interrupt,
+                    "serial interrupt",
+                    &[
+                        ("rx", SerialInterrupt::Rx),
+                        ("tx", SerialInterrupt::Tx),
+                        ("rx_tx", SerialInterrupt::RxTx),
+                        ("none", SerialInterrupt::None),
+                    ],
+                )?,
+            },
+            priority: Priority::from_yaml(&yaml["priority"], priority_bits)?,
+            location: Location::of(yaml),
+        };
+        if serial.priority.is_some() && serial.interrupt == SerialInterrupt::None {
+            return Err(Error::at(
+                &yaml["priority"],
+                format!(
+                    "Serial '{}' has a priority but does not interrupt",
+                    serial.id.lower()
+                ),
+            ));
+        }
+        Ok(serial)
+    }
+    /// The name of the generated binding for the whole serial
+    fn binding(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.id.lower().to_string())
+    }
+    /// The halves of a split serial are suffixed with '_tx' and '_rx'
+    fn tx_binding(&self) -> String {
+        format!("{}_tx", self.binding())
+    }
+    fn rx_binding(&self) -> String {
+        format!("{}_rx", self.binding())
+    }
+    /// The binding of a serial that is returned whole, its gpios are moved
+    /// into it unbound
+    fn whole_bindings(&self) -> Vec<(String, String, bool, Location)> {
+        vec![(
+            self.binding(),
+            format!("serial '{}'", self.id.lower()),
+            self.name.is_some() || I::CHOSEN_NAME,
+            self.location,
+        )]
+    }
+    /// The bindings of a serial that the stm32 hals split into its halves.
+    /// The gpios are bound by their name before they are moved into the serial.
+    fn split_bindings(&self) -> Vec<(String, String, bool, Location)> {
+        let origin = format!("serial '{}'", self.id.lower());
+        let named = self.name.is_some() || I::CHOSEN_NAME;
+        let mut bindings = vec![
+            (self.binding(), origin.clone(), named, self.location),
+            (self.tx_binding(), origin.clone(), named, self.location),
+            (self.rx_binding(), origin, named, self.location),
+        ];
+        for pin in [&self.tx, &self.rx] {
+            bindings.push((
+                pin.binding(),
+                format!("serial gpio '{}'", pin.name()),
+                false,
+                self.location,
+            ));
+        }
+        bindings
+    }
+    /// The usart frame of the stm32 holds the data bits and the parity bit
+    /// and can be either 8 or 9 bits long.
+    fn frame_length(&self) -> Result<FrameLength, String> {
+        let parity_bits = match self.parity {
+            Parity::None => 0,
+            Parity::Even | Parity::Odd => 1,
+        };
+        match self.word_length + parity_bits {
+            8 => Ok(FrameLength::Bits8),
+            9 => Ok(FrameLength::Bits9),
+            _ => Err(format!(
+                "Serial '{}' supports 8 or 9 bit frames, but {} data bits and parity '{:?}' need {} bits",
+                self.id.lower(),
+                self.word_length,
+                self.parity,
+                self.word_length + parity_bits
+            )),
+        }
+    }
+}
+
+/// The serial events that trigger the interrupt of the serial
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SerialInterrupt {
+    None,
+    Rx,
+    Tx,
+    RxTx,
+}
+
+impl SerialInterrupt {
+    /// interrupt if a byte was received
+    fn rx(&self) -> bool {
+        matches!(self, SerialInterrupt::Rx | SerialInterrupt::RxTx)
+    }
+    /// interrupt if a byte can be transmitted
+    fn tx(&self) -> bool {
+        matches!(self, SerialInterrupt::Tx | SerialInterrupt::RxTx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+impl Parity {
+    /// The function of the serial config in the stm32 hals
+    fn config_function_name(&self) -> &str {
+        match self {
+            Parity::None => "parity_none",
+            Parity::Even => "parity_even",
+            Parity::Odd => "parity_odd",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StopBits {
+    Stop0p5,
+    Stop1,
+    Stop1p5,
+    Stop2,
+}
+
+impl StopBits {
+    /// The variant of `StopBits` in the stm32 hals
+    fn ident(&self) -> &str {
+        match self {
+            StopBits::Stop0p5 => "STOP0P5",
+            StopBits::Stop1 => "STOP1",
+            StopBits::Stop1p5 => "STOP1P5",
+            StopBits::Stop2 => "STOP2",
+        }
+    }
+}
+
+/// The length of a usart frame with the parity bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameLength {
+    Bits8,
+    Bits9,
+}
+
+#[cfg(test)]
+fn messages(errors: Vec<Error>) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 impl DeviceConfig {
-    pub(crate) fn from_yaml(yaml: &Yaml) -> Self {
-        let kind = match yaml["kind"]
-            .as_str()
-            .expect("cannot parse device kind")
-            .to_lowercase()
-            .as_str()
+    /// Parses the configuration and reports all errors at once
+    pub(crate) fn from_yaml(yaml: &Yaml) -> Result<Self, Vec<Error>> {
+        let kind = match &yaml["kind"] {
+            Yaml::String(kind) => kind.to_lowercase(),
+            Yaml::BadValue => return Err(vec![Error::at(yaml, "Missing device 'kind'")]),
+            other => {
+                return Err(vec![Error::at(
+                    other,
+                    format!("Unable to parse device kind {}", describe(other)),
+                )])
+            }
+        };
+        let backends = backends();
+        let backend = match backends
+            .iter()
+            .find(|backend| backend.kinds.contains(&kind.as_str()))
         {
-            "stm32f1xx" | "bluepill" => {
-                DeviceKind::Stm32f1xx(Stm32f1xxPeripherals::from_yaml(&yaml))
+            Some(backend) => backend,
+            None => {
+                let kinds: Vec<&str> = backends
+                    .iter()
+                    .flat_map(|backend| backend.kinds.iter().copied())
+                    .collect();
+                return Err(vec![Error::at(
+                    &yaml["kind"],
+                    format!("Unknown device kind \"{}\" ({})", kind, kinds.join(", ")),
+                )]);
             }
-            other => panic!("Unknown device kind \"{}\"", other),
         };
-        let clock = yaml["clock"].as_str().map(|c| Hertz::from_str(c));
-        Self {
-            kind,
-            clock: clock.expect("Unable to parse clock"),
+        // the peripherals are parsed even with invalid clocks to report their errors too
+        let (clocks, mut errors) = match Clocks::from_yaml(yaml) {
+            Ok(clocks) => (clocks, Vec::new()),
+            Err(errors) => (Clocks::default(), errors),
+        };
+        match (backend.from_yaml)(yaml, clocks) {
+            Ok(device) if errors.is_empty() => Ok(Self { device }),
+            Ok(_) => Err(errors),
+            Err(mut peripheral_errors) => {
+                errors.append(&mut peripheral_errors);
+                Err(errors)
+            }
         }
     }
-    pub(crate) fn get_init_fn(&self) -> (Vec<syn::Stmt>, syn::Type) {
-        match self.kind {
-            DeviceKind::Stm32f1xx(_) => init_stmts_and_return_tys(&self),
-        }
+    /// Returns the statements and the return type of the init function
+    /// and the definitions of the returned structs
+    pub(crate) fn get_init_fn(
+        &self,
+        item_struct: &syn::ItemStruct,
+    ) -> (Vec<syn::Stmt>, syn::Type, Vec<syn::Item>) {
+        self.device.init_fn(item_struct)
+    }
+    /// Associated constants with the frequencies of the configured clocks
+    pub(crate) fn get_clock_consts(&self) -> Vec<syn::ImplItem> {
+        self.device.clock_consts()
     }
+    pub(crate) fn get_interrupt_unmasks(&self) -> Vec<syn::Stmt> {
+        self.device.interrupt_unmasks()
+    }
+}
+
+#[test]
+fn clocks_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+clocks:
+  hse: 8mhz
+  sysclk: 72mhz
+  pclk1: 36mhz
+",
+    )
+    .unwrap();
+    assert_eq!(
+        Clocks::from_yaml(&yaml[0]),
+        Ok(Clocks {
+            hse: Some(Hertz(8_000_000)),
+            sysclk: Some(Hertz(72_000_000)),
+            pclk1: Some(Hertz(36_000_000)),
+            ..Clocks::default()
+        })
+    );
+    let yaml = yaml_rust::YamlLoader::load_from_str("clock: 36mhz").unwrap();
+    assert_eq!(
+        Clocks::from_yaml(&yaml[0]),
+        Ok(Clocks {
+            sysclk: Some(Hertz(36_000_000)),
+            ..Clocks::default()
+        })
+    );
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+clocks:
+  sysclk: 72
+  pll: 8mhz
+",
+    )
+    .unwrap();
+    let errors: Vec<String> = Clocks::from_yaml(&yaml[0])
+        .unwrap_err()
+        .into_iter()
+        .map(|error| error.message)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "Unknown clock 'pll' (hse, sysclk, hclk, pclk1, pclk2 or adcclk)",
+            "Unable to parse frequency 72 (e.g. '8mhz')"
+        ]
+    );
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+clocks:
+  hse: 0mhz
+  hclk: 0hz
+",
+    )
+    .unwrap();
+    let errors: Vec<String> = Clocks::from_yaml(&yaml[0])
+        .unwrap_err()
+        .into_iter()
+        .map(|error| error.message)
+        .collect();
+    assert_eq!(
+        errors,
+        vec!["Clock 'hse' cannot be 0hz", "Clock 'hclk' cannot be 0hz"]
+    );
+    let yaml = yaml_rust::YamlLoader::load_from_str("{kind: stm32f1xx, clock: 0mhz}").unwrap();
+    let errors = DeviceConfig::from_yaml(&yaml[0]).unwrap_err();
+    assert_eq!(errors[0].message, "Clock 'sysclk' cannot be 0hz");
+}
+
+#[test]
+fn duty_test() {
+    assert_eq!(parse_fraction("75%", "duty"), Ok(7_500));
+    assert_eq!(parse_fraction("12.5 %", "duty"), Ok(1_250));
+    assert_eq!(parse_fraction("0.3", "duty"), Ok(3_000));
+    assert!(parse_fraction("120%", "duty").is_err());
+    assert_eq!(Duty::from_yaml(&Yaml::Integer(1)), Ok(Some(Duty(10_000))));
+    assert_eq!(Duty::from_yaml(&Yaml::BadValue), Ok(None));
+}
+
+#[test]
+fn pwm_pins_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str("{pins: [pa0, {pa1: , name: fan}]}").unwrap();
+    let pins: Vec<(String, Option<String>)> = pwm_pins_from_yaml(&yaml[0], true).unwrap();
+    assert_eq!(
+        pins,
+        vec![
+            ("pa0".to_string(), None),
+            ("pa1".to_string(), Some("fan".to_string()))
+        ]
+    );
+    let yaml = yaml_rust::YamlLoader::load_from_str("{timer: tim2, pins: []}").unwrap();
+    let error = pwm_pins_from_yaml::<String>(&yaml[0], true).unwrap_err();
+    assert_eq!(error.message, "A pwm needs at least one pin");
+}
+
+#[test]
+fn kind_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str("kind: Bluepill").unwrap();
+    assert!(DeviceConfig::from_yaml(&yaml[0]).is_ok());
+    let yaml = yaml_rust::YamlLoader::load_from_str("kind: esp32").unwrap();
+    let errors = DeviceConfig::from_yaml(&yaml[0]).unwrap_err();
+    assert_eq!(
+        errors[0].message,
+        "Unknown device kind \"esp32\" (stm32f1xx, bluepill, stm32f4xx, stm32f401, stm32f411, nrf52840, rp2040, pico, host)"
+    );
 }
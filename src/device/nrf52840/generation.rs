@@ -0,0 +1,421 @@
+use std::collections::{BTreeSet, HashSet};
+
+use quote::format_ident;
+use syn::{parse_quote, parse_str, Ident, Item, ItemStruct, Stmt};
+
+use crate::device::{
+    generation::{
+        fields, frequency_consts, nvic_unmasks, scaled_duty, static_init_and_return,
+        ComponentGroups, Initialized,
+    },
+    used_gpios, InterruptMode, PeripheralId, PinMode, DEFAULT_PWM_FREQUENCY,
+};
+
+use super::{gpio_ident, Nrf52840Peripherals, Pin, Port, HFCLK, NVIC_PRIO_BITS};
+
+/// The type of a gpio in the mode, interrupting inputs are handed to
+/// the GPIOTE as type erased pins
+fn gpio_type(pin: Pin, port: Port, mode: PinMode, degraded: bool) -> syn::Type {
+    let pin_type = match degraded {
+        true => "Pin".to_string(),
+        false => format!("p{}::P{}_{:02}", port.number(), port.number(), pin.0),
+    };
+    let mode = match mode {
+        PinMode::InputFloating => "Input<nrf52840_hal::gpio::Floating>",
+        PinMode::InputPullUp => "Input<nrf52840_hal::gpio::PullUp>",
+        PinMode::InputPullDown => "Input<nrf52840_hal::gpio::PullDown>",
+        PinMode::OutputPushPull => "Output<nrf52840_hal::gpio::PushPull>",
+        PinMode::OutputOpenDrain => "Output<nrf52840_hal::gpio::OpenDrain>",
+    };
+    parse_str(&format!(
+        "nrf52840_hal::gpio::{}<nrf52840_hal::gpio::{}>",
+        pin_type, mode
+    ))
+    .unwrap()
+}
+
+/// The type of a peripheral in the hal (e.g. `timer::Timer`) that owns
+/// the register block of the pac (e.g. `TIMER0`)
+fn peripheral_type(hal_type: &str, registers: &str) -> syn::Type {
+    parse_str(&format!(
+        "nrf52840_hal::{}<nrf52840_hal::pac::{}>",
+        hal_type, registers
+    ))
+    .unwrap()
+}
+
+/// The function of the GPIOTE channel that selects the edge
+fn gpiote_function_name(mode: InterruptMode) -> &'static str {
+    match mode {
+        InterruptMode::None => {
+            panic!("InterruptMode::None cannot be converted into a GPIOTE event")
+        }
+        InterruptMode::Rising => "lo_to_hi",
+        InterruptMode::Falling => "hi_to_lo",
+        InterruptMode::RisingFalling => "toggle",
+    }
+}
+
+pub struct DeviceInit {
+    init_block: Vec<syn::Stmt>,
+    peripherals: Ident,
+    gpios: HashSet<String>,
+}
+
+fn port_ident(port: Port) -> Ident {
+    format_ident!("port{}", port.number())
+}
+
+impl DeviceInit {
+    /// Starts the clocks and splits the ports of all used gpios
+    fn new(peripheral_config: &Nrf52840Peripherals) -> Self {
+        let peripherals = format_ident!("peripherals");
+        let mut init_block: Vec<Stmt> = parse_quote!(
+            use nrf52840_hal::prelude::*;
+            let #peripherals = nrf52840_hal::pac::Peripherals::take().unwrap();
+        );
+        if peripheral_config.external_oscillator {
+            // the hal keeps the crystal running when the clocks are dropped
+            init_block.push(parse_quote!(
+                let _ = nrf52840_hal::clocks::Clocks::new(#peripherals.CLOCK).enable_ext_hfosc();
+            ));
+        }
+        // expand: let portx = gpio::px::Parts::new(peripherals.PX);
+        let gpios = used_gpios(&peripheral_config.components());
+        let ports: BTreeSet<&Port> = gpios.iter().map(|(_, port)| port).collect();
+        for port in ports {
+            let port_ident = port_ident(*port);
+            let port_module = format_ident!("p{}", port.number());
+            let port_upper = format_ident!("P{}", port.number());
+            init_block.push(parse_quote!(
+                let #port_ident = nrf52840_hal::gpio::#port_module::Parts::new(#peripherals.#port_upper);
+            ));
+        }
+        Self {
+            init_block,
+            peripherals,
+            gpios: gpios
+                .into_iter()
+                .map(|(pin, port)| gpio_ident(pin, port))
+                .collect(),
+        }
+    }
+    /// Takes the gpio from the pool and returns its field in the port,
+    /// e.g. `port0.p0_13`
+    fn take_gpio(&mut self, (pin, port): (Pin, Port)) -> syn::Expr {
+        let port_ident = port_ident(port);
+        let pin_name = format_ident!(
+            "{}",
+            self.gpios
+                .take(&gpio_ident(pin, port))
+                .expect("Use of uninitialized gpio")
+        );
+        parse_quote!(#port_ident.#pin_name)
+    }
+    /// Takes the gpio from the pool and returns the expression that brings
+    /// it into the given mode, e.g. `port0.p0_13.into_pullup_input()`
+    fn gpio_into(&mut self, pin: (Pin, Port), mode: PinMode) -> syn::Expr {
+        let gpio = self.take_gpio(pin);
+        match mode {
+            PinMode::InputFloating => parse_quote!(#gpio.into_floating_input()),
+            PinMode::InputPullUp => parse_quote!(#gpio.into_pullup_input()),
+            PinMode::InputPullDown => parse_quote!(#gpio.into_pulldown_input()),
+            PinMode::OutputPushPull => parse_quote!(
+                #gpio.into_push_pull_output(nrf52840_hal::gpio::Level::Low)
+            ),
+            PinMode::OutputOpenDrain => parse_quote!(
+                #gpio.into_open_drain_output(
+                    nrf52840_hal::gpio::OpenDrainConfig::Standard0Disconnect1,
+                    nrf52840_hal::gpio::Level::High
+                )
+            ),
+        }
+    }
+    /// Initializes the inputs and connects the interrupting ones to a
+    /// GPIOTE channel each
+    fn inputs(
+        &mut self,
+        peripheral_config: &Nrf52840Peripherals,
+    ) -> (Vec<Initialized>, Vec<Initialized>) {
+        let mut idents = Vec::new();
+        let gpiote_inputs = peripheral_config
+            .gpiote_inputs()
+            .unwrap_or_else(|errors| panic!("{}", errors[0]));
+        let gpiote_ident = format_ident!("gpiote");
+        let gpiote = match gpiote_inputs.is_empty() {
+            true => Vec::new(),
+            false => {
+                let peripherals_ident = &self.peripherals;
+                self.init_block.push(parse_quote!(
+                    let #gpiote_ident = nrf52840_hal::gpiote::Gpiote::new(#peripherals_ident.GPIOTE);
+                ));
+                // the interrupt handler has to reset the events of the channels
+                vec![Initialized {
+                    ty: parse_quote!(nrf52840_hal::gpiote::Gpiote),
+                    ident: gpiote_ident.clone(),
+                }]
+            }
+        };
+        let mut channels = 0usize..;
+        for gpio in &peripheral_config.gpio.input {
+            let gpio_ident = format_ident!("{}", gpio.binding());
+            let init = self.gpio_into(gpio.pin, gpio.mode);
+            let degraded = gpio.interrupt_mode != InterruptMode::None;
+            match gpio.interrupt_mode {
+                InterruptMode::None => self.init_block.push(parse_quote!(
+                    let #gpio_ident = #init;
+                )),
+                other => {
+                    let channel = format_ident!("channel{}", channels.next().unwrap());
+                    let edge = format_ident!("{}", gpiote_function_name(other));
+                    // expand:
+                    // let pxy = portx.pxy.into_mode().degrade();
+                    // gpiote.channelz().input_pin(&pxy).edge().enable_interrupt();
+                    self.init_block.append(&mut parse_quote!(
+                        let #gpio_ident = #init.degrade();
+                        #gpiote_ident.#channel().input_pin(&#gpio_ident).#edge().enable_interrupt();
+                    ));
+                }
+            }
+            let (pin, port) = gpio.pin;
+            idents.push(Initialized {
+                ty: gpio_type(pin, port, gpio.mode, degraded),
+                ident: gpio_ident,
+            });
+        }
+        (idents, gpiote)
+    }
+    fn outputs(&mut self, peripheral_config: &Nrf52840Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for gpio in &peripheral_config.gpio.output {
+            let gpio_ident = format_ident!("{}", gpio.binding());
+            let init = self.gpio_into(gpio.pin, gpio.mode);
+            self.init_block.push(parse_quote!(
+                let mut #gpio_ident = #init;
+            ));
+            let (pin, port) = gpio.pin;
+            idents.push(Initialized {
+                ty: gpio_type(pin, port, gpio.mode, false),
+                ident: gpio_ident,
+            });
+        }
+        idents
+    }
+
+    fn timers(&mut self, peripheral_config: &Nrf52840Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        let peripherals_ident = &self.peripherals;
+        for timer in &peripheral_config.timer {
+            let id = timer.id;
+            let timer_ident = format_ident!("{}", id.lower());
+            let timer_upper = format_ident!("{}", id.upper());
+            // expand: let timerx = Timer::new(peripherals.TIMERX);
+            self.init_block.push(parse_quote!(
+                let #timer_ident = nrf52840_hal::timer::Timer::new(#peripherals_ident.#timer_upper);
+            ));
+            idents.push(Initialized {
+                ty: peripheral_type("timer::Timer", id.upper()),
+                ident: timer_ident,
+            });
+        }
+        idents
+    }
+
+    fn pwms(&mut self, peripheral_config: &Nrf52840Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for pwm in &peripheral_config.pwm {
+            let id = pwm.id;
+            let pwm_ident = format_ident!("{}", pwm.binding());
+            let pwm_upper = format_ident!("{}", id.upper());
+            let peripherals_ident = &self.peripherals;
+            let frequency = pwm.frequency.unwrap_or(DEFAULT_PWM_FREQUENCY).0 as u32;
+            // expand:
+            // let pwmx = Pwm::new(peripherals.PWMX);
+            // pwmx.set_period(freq.hz());
+            self.init_block.append(&mut parse_quote!(
+                let #pwm_ident = nrf52840_hal::pwm::Pwm::new(#peripherals_ident.#pwm_upper);
+                #pwm_ident.set_period(#frequency.hz());
+            ));
+            // the channels are connected in the order of the pins
+            for (channel, pin) in pwm.pins.iter().enumerate() {
+                let channel = format_ident!("C{}", channel);
+                let init = self.gpio_into(*pin, PinMode::OutputPushPull);
+                // expand: pwmx.set_output_pin(Channel::Cz, portx.pxy.into_push_pull_output(Level::Low).degrade());
+                self.init_block.push(parse_quote!(
+                    #pwm_ident.set_output_pin(nrf52840_hal::pwm::Channel::#channel, #init.degrade());
+                ));
+            }
+            if let Some(duty) = pwm.duty {
+                let duty = scaled_duty(parse_quote!(#pwm_ident.max_duty()), duty);
+                // expand: pwmx.set_duty_on_common(duty);
+                self.init_block.push(parse_quote!(
+                    #pwm_ident.set_duty_on_common(#duty);
+                ));
+            }
+            idents.push(Initialized {
+                ty: peripheral_type("pwm::Pwm", id.upper()),
+                ident: pwm_ident,
+            });
+        }
+        idents
+    }
+
+    fn serials(&mut self, peripheral_config: &Nrf52840Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for serial in &peripheral_config.serial {
+            let id = serial.id;
+            // the transmit line idles high
+            let tx = self.take_gpio(serial.tx);
+            let rx = self.gpio_into(serial.rx, PinMode::InputFloating);
+            let peripherals_ident = &self.peripherals;
+            let serial_ident = format_ident!("{}", serial.binding());
+            let serial_upper = format_ident!("{}", id.upper());
+            let parity = format_ident!(
+                "{}",
+                match serial.parity {
+                    true => "INCLUDED",
+                    false => "EXCLUDED",
+                }
+            );
+            let baud_rate = format_ident!("{}", serial.baud_rate_variant());
+            // expand: let uartex = Uarte::new(peripherals.UARTEX, Pins {..}, Parity::X, Baudrate::BAUDX);
+            self.init_block.push(parse_quote!(
+                let #serial_ident = nrf52840_hal::uarte::Uarte::new(
+                    #peripherals_ident.#serial_upper,
+                    nrf52840_hal::uarte::Pins {
+                        txd: #tx.into_push_pull_output(nrf52840_hal::gpio::Level::High).degrade(),
+                        rxd: #rx.degrade(),
+                        cts: None,
+                        rts: None,
+                    },
+                    nrf52840_hal::uarte::Parity::#parity,
+                    nrf52840_hal::uarte::Baudrate::#baud_rate
+                );
+            ));
+            idents.push(Initialized {
+                ty: peripheral_type("uarte::Uarte", id.upper()),
+                ident: serial_ident,
+            });
+        }
+        idents
+    }
+
+    pub(crate) fn get_init_block(
+        peripheral_config: &Nrf52840Peripherals,
+        item_struct: &ItemStruct,
+    ) -> (Vec<Stmt>, syn::Type, Vec<Item>) {
+        let mut device_init = DeviceInit::new(peripheral_config);
+        let (inputs, gpiote) = device_init.inputs(peripheral_config);
+        let outputs = device_init.outputs(peripheral_config);
+        let timer = device_init.timers(peripheral_config);
+        let pwm = device_init.pwms(peripheral_config);
+        let serial = device_init.serials(peripheral_config);
+        let mut input_fields = fields(&inputs);
+        input_fields.append(&mut fields(&gpiote));
+        static_init_and_return(
+            device_init.init_block,
+            item_struct,
+            ComponentGroups {
+                inputs: input_fields,
+                outputs: fields(&outputs),
+                timers: fields(&timer),
+                pwm: fields(&pwm),
+                serial: fields(&serial),
+            },
+        )
+    }
+}
+
+/// The HFCLK cannot be configured, it always runs at 64mhz
+pub(crate) fn clock_consts() -> Vec<syn::ImplItem> {
+    frequency_consts(&[("SYSCLK", HFCLK)])
+}
+
+/// Unmasks the GPIOTE interrupt in the NVIC if any input interrupts
+pub(crate) fn interrupt_unmasks(peripheral_config: &Nrf52840Peripherals) -> Vec<Stmt> {
+    let interrupts = peripheral_config
+        .interrupts()
+        .unwrap_or_else(|errors| panic!("{}", errors[0]));
+    nvic_unmasks(
+        &parse_quote!(nrf52840_hal::pac),
+        &interrupts,
+        NVIC_PRIO_BITS,
+    )
+}
+
+#[test]
+fn init_block_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: nrf52840
+clocks:
+  hse: 32mhz
+gpio:
+  input:
+    - P0.11:
+      mode: pull_up
+      interrupt: falling
+      priority: 1
+    - p0.12:
+      mode: pull_up
+  output:
+    - p1.09: push_pull
+      name: led
+pwm:
+  - pwm0:
+    pins:
+      - p0.13
+    duty: 50%
+serial:
+  - uarte0:
+    tx: p0.06
+    rx: p0.08
+    baud: 115200
+",
+    )
+    .unwrap();
+    let config = crate::device::DeviceConfig::from_yaml(&yaml[0]).unwrap();
+    let item_struct: ItemStruct = parse_quote!(
+        struct Board {}
+    );
+    let (init_block, _, items) = config.get_init_fn(&item_struct);
+    let init_block = quote::quote!(#(#init_block)*).to_string();
+    for expected in [
+        quote::quote!(let p0_11 = port0.p0_11.into_pullup_input().degrade();),
+        quote::quote!(gpiote.channel0().input_pin(&p0_11).hi_to_lo().enable_interrupt();),
+        quote::quote!(let mut led = port1.p1_09.into_push_pull_output(nrf52840_hal::gpio::Level::Low);),
+        quote::quote!(pwm0.set_output_pin(nrf52840_hal::pwm::Channel::C0, port0.p0_13.into_push_pull_output(nrf52840_hal::gpio::Level::Low).degrade());),
+        quote::quote!(txd: port0.p0_06.into_push_pull_output(nrf52840_hal::gpio::Level::High).degrade(),),
+        quote::quote!(nrf52840_hal::uarte::Baudrate::BAUD115200),
+    ] {
+        assert!(
+            init_block.contains(&expected.to_string()),
+            "{} in {}",
+            expected,
+            init_block
+        );
+    }
+    let inputs = &items[0];
+    #[rustfmt::skip]
+    let expected: Item = parse_quote!(
+        struct BoardInputs {
+            pub p0_11: nrf52840_hal::gpio::Pin<nrf52840_hal::gpio::Input<nrf52840_hal::gpio::PullUp>>,
+            pub p0_12: nrf52840_hal::gpio::p0::P0_12<nrf52840_hal::gpio::Input<nrf52840_hal::gpio::PullUp>>,
+            pub gpiote: nrf52840_hal::gpiote::Gpiote,
+        }
+    );
+    assert_eq!(
+        quote::quote!(#inputs).to_string(),
+        quote::quote!(#expected).to_string()
+    );
+    let unmasks = config.get_interrupt_unmasks();
+    assert_eq!(
+        quote::quote!(#(#unmasks)*).to_string(),
+        quote::quote!(
+            let mut nvic = nrf52840_hal::pac::CorePeripherals::steal().NVIC;
+            nvic.set_priority(nrf52840_hal::pac::Interrupt::GPIOTE, 32u8);
+            nrf52840_hal::pac::NVIC::unmask(nrf52840_hal::pac::Interrupt::GPIOTE);
+        )
+        .to_string()
+    );
+}
@@ -0,0 +1,726 @@
+//! The nrf52840.
+//! Its gpios are named by port and pin (e.g. 'p0.13') and every peripheral
+//! can be connected to any gpio, so there are no pin tables. Inputs
+//! interrupt through the channels of the GPIOTE, the serials are UARTEs
+//! and pwm is generated by the PWM instances instead of timers.
+use std::collections::BTreeMap;
+use yaml_rust::Yaml;
+
+mod generation;
+
+use self::generation::DeviceInit;
+
+use super::{
+    check_components, check_unique, clocks_yaml, component_key, keyword_from_yaml,
+    merge_interrupts, name_from_yaml, parse_list, pin_from_yaml, pwm_pins_from_yaml, required,
+    Baud, Clocks, Component, Device, Duty, GpioPin, Hertz, InterruptMode, PeripheralId, Priority,
+};
+use crate::error::{describe, Error, Location};
+
+#[cfg(test)]
+use super::messages;
+
+type Gpio = super::Gpio<(Pin, Port)>;
+type Gpios = super::Gpios<(Pin, Port)>;
+type Timer = super::Timer<TimerID>;
+
+/// Bindings of the generated init function that are not components
+const RESERVED_BINDINGS: &[&str] = &["peripherals", "gpiote", "port0", "port1"];
+
+/// The nrf52840 implements the upper 3 bits of the 8 bit interrupt priority
+const NVIC_PRIO_BITS: u8 = 3;
+
+/// The high frequency clock of the cpu, it cannot be changed
+const HFCLK: Hertz = Hertz(64_000_000);
+/// The external crystal that can replace the internal oscillator as source of the HFCLK
+const HFXO: Hertz = Hertz(32_000_000);
+
+/// Each channel of the GPIOTE serves one interrupting input
+const GPIOTE_CHANNELS: usize = 8;
+
+/// The pwm counter runs with 16mhz divided by a prescaler of up to 128
+/// and counts up to 32767
+const PWM_MIN_FREQUENCY: Hertz = Hertz(4);
+const PWM_MAX_FREQUENCY: Hertz = Hertz(16_000_000);
+
+/// The baud rates of the UARTE baud rate register and their variants
+/// of the hal's `Baudrate`
+const BAUD_RATES: &[(u32, &str)] = &[
+    (1200, "BAUD1200"),
+    (2400, "BAUD2400"),
+    (4800, "BAUD4800"),
+    (9600, "BAUD9600"),
+    (14400, "BAUD14400"),
+    (19200, "BAUD19200"),
+    (28800, "BAUD28800"),
+    (31250, "BAUD31250"),
+    (38400, "BAUD38400"),
+    (56000, "BAUD56000"),
+    (57600, "BAUD57600"),
+    (76800, "BAUD76800"),
+    (115200, "BAUD115200"),
+    (230400, "BAUD230400"),
+    (250000, "BAUD250000"),
+    (460800, "BAUD460800"),
+    (921600, "BAUD921600"),
+    (1000000, "BAUD1M"),
+];
+
+#[derive(Debug)]
+pub struct Nrf52840Peripherals {
+    gpio: Gpios,
+    timer: Vec<Timer>,
+    pwm: Vec<PWM>,
+    serial: Vec<Serial>,
+    /// Whether the external crystal drives the HFCLK
+    external_oscillator: bool,
+}
+
+impl Device for Nrf52840Peripherals {
+    fn kinds() -> &'static [&'static str] {
+        &["nrf52840"]
+    }
+    /// Parses the peripherals and checks them against the hardware constraints.
+    /// The constraints are only checked if all components could be parsed.
+    fn from_yaml(yaml: &Yaml, clocks: Clocks) -> Result<Self, Vec<Error>> {
+        let mut errors = Vec::new();
+        let peripherals = Self {
+            gpio: Gpios::from_yaml(&yaml["gpio"], NVIC_PRIO_BITS, &mut errors),
+            timer: parse_list(&yaml["timer"], Timer::from_yaml, &mut errors),
+            pwm: parse_list(&yaml["pwm"], PWM::from_yaml, &mut errors),
+            serial: parse_list(&yaml["serial"], Serial::from_yaml, &mut errors),
+            external_oscillator: clocks.hse.is_some(),
+        };
+        if errors.is_empty() {
+            errors = peripherals.check();
+        }
+        for error in check_clocks(clocks) {
+            errors.push(Error::at(clocks_yaml(yaml), error));
+        }
+        if errors.is_empty() {
+            Ok(peripherals)
+        } else {
+            Err(errors)
+        }
+    }
+    fn init_fn(
+        &self,
+        item_struct: &syn::ItemStruct,
+    ) -> (Vec<syn::Stmt>, syn::Type, Vec<syn::Item>) {
+        DeviceInit::get_init_block(self, item_struct)
+    }
+    fn clock_consts(&self) -> Vec<syn::ImplItem> {
+        generation::clock_consts()
+    }
+    fn interrupt_unmasks(&self) -> Vec<syn::Stmt> {
+        generation::interrupt_unmasks(self)
+    }
+}
+
+/// The HFCLK always runs at 64mhz, the clock section can only select
+/// the 32mhz crystal as its source.
+fn check_clocks(clocks: Clocks) -> Vec<String> {
+    let mut errors = Vec::new();
+    match clocks.hse {
+        Some(hse) if hse != HFXO => errors.push(format!(
+            "Clock 'hse' of {} is not supported, the external crystal has {}",
+            hse, HFXO
+        )),
+        _ => {}
+    }
+    match clocks.sysclk {
+        Some(sysclk) if sysclk != HFCLK => errors.push(format!(
+            "Clock 'sysclk' of {} is not supported, the nrf52840 runs at {}",
+            sysclk, HFCLK
+        )),
+        _ => {}
+    }
+    let fixed = [
+        ("hclk", clocks.hclk),
+        ("pclk1", clocks.pclk1),
+        ("pclk2", clocks.pclk2),
+        ("adcclk", clocks.adcclk),
+    ];
+    for (name, frequency) in fixed.iter() {
+        if frequency.is_some() {
+            errors.push(format!("Clock '{}' does not exist on the nrf52840", name));
+        }
+    }
+    errors
+}
+
+impl Nrf52840Peripherals {
+    /// Checks the pwms against the channels and the frequency range of the
+    /// PWM instances and the interrupting inputs against the GPIOTE channels.
+    fn check(&self) -> Vec<Error> {
+        let mut errors = check_unique(self.timer.iter().map(|timer| {
+            (
+                timer.id,
+                format!("Timer '{}'", timer.id.lower()),
+                timer.location,
+            )
+        }));
+        errors.append(&mut check_unique(self.pwm.iter().map(|pwm| {
+            (pwm.id, format!("Pwm '{}'", pwm.id.lower()), pwm.location)
+        })));
+        errors.append(&mut check_unique(self.serial.iter().map(|serial| {
+            (
+                serial.id,
+                format!("Serial '{}'", serial.id.lower()),
+                serial.location,
+            )
+        })));
+        for pwm in &self.pwm {
+            if pwm.pins.len() > 4 {
+                errors.push(Error::new(
+                    pwm.location,
+                    format!(
+                        "Pwm '{}' has 4 channels, but {} gpios are configured",
+                        pwm.id.lower(),
+                        pwm.pins.len()
+                    ),
+                ));
+            }
+            if let Some(frequency) = pwm.frequency {
+                if frequency < PWM_MIN_FREQUENCY || frequency > PWM_MAX_FREQUENCY {
+                    errors.push(Error::new(
+                        pwm.location,
+                        format!(
+                            "Pwm frequency {} of '{}' is not between {} and {}",
+                            frequency,
+                            pwm.id.lower(),
+                            PWM_MIN_FREQUENCY,
+                            PWM_MAX_FREQUENCY
+                        ),
+                    ));
+                }
+            }
+        }
+        errors.append(&mut check_components(RESERVED_BINDINGS, &self.components()));
+        if let Err(mut interrupt_errors) = self.interrupts() {
+            errors.append(&mut interrupt_errors);
+        }
+        errors
+    }
+    /// The interrupting inputs, each gets its own GPIOTE channel in order
+    fn gpiote_inputs(&self) -> Result<Vec<&Gpio>, Vec<Error>> {
+        let inputs: Vec<&Gpio> = self
+            .gpio
+            .input
+            .iter()
+            .filter(|gpio| gpio.interrupt_mode != InterruptMode::None)
+            .collect();
+        match inputs.get(GPIOTE_CHANNELS) {
+            Some(gpio) => Err(vec![Error::new(
+                gpio.location,
+                format!(
+                    "Gpio '{}' cannot interrupt, the GPIOTE has only {} channels",
+                    gpio.pin.name(),
+                    GPIOTE_CHANNELS
+                ),
+            )]),
+            None => Ok(inputs),
+        }
+    }
+    /// All nvic interrupts of the configured sources and their priority.
+    /// All inputs share the GPIOTE interrupt.
+    fn interrupts(&self) -> Result<BTreeMap<String, Option<Priority>>, Vec<Error>> {
+        let inputs = self.gpiote_inputs()?;
+        merge_interrupts(
+            inputs
+                .into_iter()
+                .map(|gpio| {
+                    (
+                        "GPIOTE".to_string(),
+                        gpio.pin.name(),
+                        gpio.priority,
+                        gpio.location,
+                    )
+                })
+                .collect(),
+        )
+    }
+    /// The components in the order of the yaml sections
+    fn components(&self) -> [&dyn Component<(Pin, Port)>; 4] {
+        [&self.gpio, &self.timer, &self.pwm, &self.serial]
+    }
+}
+
+/// A UARTE, the hal does not split it into a transmitting and a
+/// receiving half
+#[derive(Debug)]
+pub struct Serial {
+    id: SerialID,
+    /// Binding and field name chosen in the yaml
+    name: Option<String>,
+    rx: (Pin, Port),
+    tx: (Pin, Port),
+    baud_rate: Baud,
+    /// The UARTE only generates even parity
+    parity: bool,
+    location: Location,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum SerialID {
+    Uarte0,
+    Uarte1,
+}
+
+impl Serial {
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        let id = SerialID::from_yaml(component_key(yaml, "serial", SerialID::EXAMPLE)?)?;
+        let baud_yaml = required(yaml, "baud", "serial")?;
+        let baud_rate = Baud::from_yaml(baud_yaml)?;
+        if !BAUD_RATES.iter().any(|(rate, _)| *rate == baud_rate.0) {
+            let baud_rates: Vec<String> = BAUD_RATES
+                .iter()
+                .map(|(rate, _)| rate.to_string())
+                .collect();
+            return Err(Error::at(
+                baud_yaml,
+                format!(
+                    "Baud rate {} is not supported by the UARTE ({})",
+                    baud_rate.0,
+                    baud_rates.join(", ")
+                ),
+            ));
+        }
+        // the frame format of the UARTE is fixed apart from the parity
+        let unsupported = [
+            ("stop_bits", Yaml::Integer(1)),
+            ("word_length", Yaml::Integer(8)),
+        ];
+        for (key, supported) in unsupported.iter() {
+            match &yaml[*key] {
+                Yaml::BadValue => {}
+                value if value == supported => {}
+                other => {
+                    return Err(Error::at(
+                        other,
+                        format!(
+                            "Unsupported {} {}, the UARTE only supports {}",
+                            key.replace('_', " "),
+                            describe(other),
+                            describe(supported)
+                        ),
+                    ))
+                }
+            }
+        }
+        for key in ["interrupt", "priority", "baud_tolerance"] {
+            if !yaml[key].is_badvalue() {
+                return Err(Error::at(
+                    &yaml[key],
+                    format!("Key '{}' is not supported for the UARTE", key),
+                ));
+            }
+        }
+        Ok(Self {
+            id,
+            name: name_from_yaml(&yaml["name"])?,
+            rx: pin_from_yaml(required(yaml, "rx", "serial")?)?,
+            tx: pin_from_yaml(required(yaml, "tx", "serial")?)?,
+            baud_rate,
+            parity: match &yaml["parity"] {
+                Yaml::BadValue => false,
+                parity => keyword_from_yaml(parity, "parity", &[("none", false), ("even", true)])?,
+            },
+            location: Location::of(yaml),
+        })
+    }
+    /// The name of the generated binding
+    fn binding(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.id.lower().to_string())
+    }
+    /// The variant of the hal's `Baudrate` (e.g. `BAUD115200`)
+    fn baud_rate_variant(&self) -> &str {
+        BAUD_RATES
+            .iter()
+            .find(|(rate, _)| *rate == self.baud_rate.0)
+            .map(|(_, variant)| *variant)
+            .expect("baud rate is checked while parsing")
+    }
+}
+
+impl Component<(Pin, Port)> for Serial {
+    fn gpios(&self) -> Vec<((Pin, Port), Location)> {
+        vec![(self.tx, self.location), (self.rx, self.location)]
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        vec![(
+            self.binding(),
+            format!("serial '{}'", self.id.lower()),
+            self.name.is_some(),
+            self.location,
+        )]
+    }
+}
+
+impl PeripheralId for SerialID {
+    const EXAMPLE: &'static str = "uarte0";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml.as_str().map(str::to_lowercase).as_deref() {
+            Some("uarte0") => Ok(SerialID::Uarte0),
+            Some("uarte1") => Ok(SerialID::Uarte1),
+            _ => Err(Error::at(
+                yaml,
+                format!("Unknown serial {} (uarte0 or uarte1)", describe(yaml)),
+            )),
+        }
+    }
+    fn lower(&self) -> &str {
+        match self {
+            SerialID::Uarte0 => "uarte0",
+            SerialID::Uarte1 => "uarte1",
+        }
+    }
+}
+
+impl SerialID {
+    fn upper(&self) -> &str {
+        match self {
+            SerialID::Uarte0 => "UARTE0",
+            SerialID::Uarte1 => "UARTE1",
+        }
+    }
+}
+
+/// A PWM instance, its channels are connected to the gpios in order
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
+pub struct PWM {
+    id: PwmID,
+    /// Binding and field name chosen in the yaml
+    name: Option<String>,
+    pins: Vec<(Pin, Port)>,
+    frequency: Option<Hertz>,
+    duty: Option<Duty>,
+    location: Location,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum PwmID {
+    Pwm0,
+    Pwm1,
+    Pwm2,
+    Pwm3,
+}
+
+impl PWM {
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        let id = PwmID::from_yaml(component_key(yaml, "pwm", PwmID::EXAMPLE)?)?;
+        // the channels are named after the pwm, not the pins
+        let pins = pwm_pins_from_yaml(yaml, false)?
+            .into_iter()
+            .map(|(pin, _)| pin)
+            .collect();
+        Ok(Self {
+            id,
+            name: name_from_yaml(&yaml["name"])?,
+            pins,
+            frequency: Hertz::from_yaml(&yaml["freq"])?,
+            duty: Duty::from_yaml(&yaml["duty"])?,
+            location: Location::of(yaml),
+        })
+    }
+    /// The name of the generated binding
+    fn binding(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.id.lower().to_string())
+    }
+}
+
+impl Component<(Pin, Port)> for PWM {
+    fn gpios(&self) -> Vec<((Pin, Port), Location)> {
+        self.pins.iter().map(|pin| (*pin, self.location)).collect()
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        vec![(
+            self.binding(),
+            format!("pwm '{}'", self.id.lower()),
+            self.name.is_some(),
+            self.location,
+        )]
+    }
+}
+
+impl PeripheralId for PwmID {
+    const EXAMPLE: &'static str = "pwm0";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml.as_str().map(str::to_lowercase).as_deref() {
+            Some("pwm0") => Ok(PwmID::Pwm0),
+            Some("pwm1") => Ok(PwmID::Pwm1),
+            Some("pwm2") => Ok(PwmID::Pwm2),
+            Some("pwm3") => Ok(PwmID::Pwm3),
+            _ => Err(Error::at(
+                yaml,
+                format!("Unknown pwm {} (pwm0 to pwm3)", describe(yaml)),
+            )),
+        }
+    }
+    fn lower(&self) -> &str {
+        match self {
+            PwmID::Pwm0 => "pwm0",
+            PwmID::Pwm1 => "pwm1",
+            PwmID::Pwm2 => "pwm2",
+            PwmID::Pwm3 => "pwm3",
+        }
+    }
+}
+
+impl PwmID {
+    fn upper(&self) -> &str {
+        match self {
+            PwmID::Pwm0 => "PWM0",
+            PwmID::Pwm1 => "PWM1",
+            PwmID::Pwm2 => "PWM2",
+            PwmID::Pwm3 => "PWM3",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum TimerID {
+    Timer0,
+    Timer1,
+    Timer2,
+    Timer3,
+    Timer4,
+}
+
+impl PeripheralId for TimerID {
+    const EXAMPLE: &'static str = "timer0";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml.as_str().map(str::to_lowercase).as_deref() {
+            Some("timer0") => Ok(TimerID::Timer0),
+            Some("timer1") => Ok(TimerID::Timer1),
+            Some("timer2") => Ok(TimerID::Timer2),
+            Some("timer3") => Ok(TimerID::Timer3),
+            Some("timer4") => Ok(TimerID::Timer4),
+            _ => Err(Error::at(
+                yaml,
+                format!("Unknown timer {} (timer0 to timer4)", describe(yaml)),
+            )),
+        }
+    }
+    fn lower(&self) -> &str {
+        match self {
+            TimerID::Timer0 => "timer0",
+            TimerID::Timer1 => "timer1",
+            TimerID::Timer2 => "timer2",
+            TimerID::Timer3 => "timer3",
+            TimerID::Timer4 => "timer4",
+        }
+    }
+}
+
+impl TimerID {
+    fn upper(&self) -> &str {
+        match self {
+            TimerID::Timer0 => "TIMER0",
+            TimerID::Timer1 => "TIMER1",
+            TimerID::Timer2 => "TIMER2",
+            TimerID::Timer3 => "TIMER3",
+            TimerID::Timer4 => "TIMER4",
+        }
+    }
+}
+
+impl GpioPin for (Pin, Port) {
+    const EXAMPLE: &'static str = "p0.13";
+    /// Parses gpio names like 'P0.13' or 'p1_02'
+    fn parse(name: &str) -> Result<Self, String> {
+        let string = name.to_lowercase();
+        let (port, pin) = string
+            .strip_prefix('p')
+            .and_then(|string| string.split_once(['.', '_']))
+            .ok_or_else(|| format!("Unable to parse gpio '{}' (e.g. 'p0.13')", name))?;
+        let port = match port {
+            "0" => Port::P0,
+            "1" => Port::P1,
+            _ => {
+                return Err(format!(
+                    "Unable to parse the port of gpio '{}' (0 or 1)",
+                    name
+                ))
+            }
+        };
+        let pin = pin
+            .parse::<usize>()
+            .map_err(|_| format!("Unable to parse the pin number of gpio '{}'", name))?;
+        Ok((Pin(pin), port))
+    }
+    fn name(&self) -> String {
+        gpio_name(self.0, self.1)
+    }
+    fn binding(&self) -> String {
+        gpio_ident(self.0, self.1)
+    }
+    fn check_exists(&self) -> Result<(), String> {
+        let (pin, port) = self;
+        if pin.0 > port.max_pin() {
+            Err(format!(
+                "Gpio '{}' does not exist, the pins of port {} are numbered from 0 to {}",
+                self.name(),
+                port.number(),
+                port.max_pin()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The name of the gpio as written in the datasheet
+fn gpio_name(pin: Pin, port: Port) -> String {
+    format!("p{}.{:02}", port.number(), pin.0)
+}
+
+/// The name of the gpio in the hal, which is also its binding
+fn gpio_ident(pin: Pin, port: Port) -> String {
+    format!("p{}_{:02}", port.number(), pin.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pin(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Port {
+    P0,
+    P1,
+}
+
+impl Port {
+    fn number(&self) -> usize {
+        match self {
+            Port::P0 => 0,
+            Port::P1 => 1,
+        }
+    }
+    /// Port 1 only has 16 pins
+    fn max_pin(&self) -> usize {
+        match self {
+            Port::P0 => 31,
+            Port::P1 => 15,
+        }
+    }
+}
+
+#[test]
+fn parse_pin_test() {
+    type Gpio = (Pin, Port);
+    assert_eq!(Gpio::parse("P0.13"), Ok((Pin(13), Port::P0)));
+    assert_eq!(Gpio::parse("p1_02"), Ok((Pin(2), Port::P1)));
+    assert_eq!(gpio_name(Pin(2), Port::P1), "p1.02");
+    assert_eq!(
+        Gpio::parse("p2.01"),
+        Err("Unable to parse the port of gpio 'p2.01' (0 or 1)".to_string())
+    );
+    assert!(Gpio::parse("pa0").is_err());
+}
+
+#[test]
+fn baud_rate_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+- uarte0:
+  tx: p0.06
+  rx: p0.08
+  baud: 1000000
+- uarte0:
+  tx: p0.06
+  rx: p0.08
+  baud: 100000
+",
+    )
+    .unwrap();
+    let serial = Serial::from_yaml(&yaml[0][0]).unwrap();
+    assert_eq!(serial.baud_rate_variant(), "BAUD1M");
+    let error = Serial::from_yaml(&yaml[0][1]).unwrap_err();
+    assert!(error
+        .message
+        .starts_with("Baud rate 100000 is not supported by the UARTE"));
+}
+
+#[test]
+fn check_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: nrf52840
+clocks:
+  hse: 8mhz
+  pclk1: 16mhz
+gpio:
+  input:
+    - p0.11:
+      mode: pull_up
+      interrupt: falling
+      priority: 1
+    - p0.12:
+      mode: pull_up
+      interrupt: rising
+      priority: 2
+    - p1.16:
+      mode: floating
+pwm:
+  - pwm0:
+    pins:
+      - p0.13
+      - p0.14
+    freq: 1hz
+serial:
+  - uarte0:
+    tx: p0.06
+    rx: p0.13
+    baud: 115200
+",
+    )
+    .unwrap();
+    let clocks = Clocks::from_yaml(&yaml[0]).unwrap();
+    let message = messages(Nrf52840Peripherals::from_yaml(&yaml[0], clocks).unwrap_err());
+    assert!(message.contains("'p0.11' and 'p0.12' share the interrupt GPIOTE"));
+    assert!(message.contains("Gpio 'p1.16' does not exist"));
+    assert!(message.contains("Gpio 'p0.13' is used multiple times"));
+    assert!(message.contains("Pwm frequency 1hz of 'pwm0' is not between 4hz and 16mhz"));
+    assert!(message.contains("Clock 'hse' of 8mhz is not supported"));
+    assert!(message.contains("Clock 'pclk1' does not exist on the nrf52840"));
+
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+- uarte1:
+  tx: p0.06
+  rx: p0.08
+  baud: 100000
+- uarte1:
+  tx: p0.06
+  rx: p0.08
+  baud: 9600
+  parity: odd
+- uarte1:
+  tx: p0.06
+  rx: p0.08
+  baud: 9600
+  stop_bits: 2
+",
+    )
+    .unwrap();
+    let errors: Vec<Error> = yaml[0]
+        .as_vec()
+        .unwrap()
+        .iter()
+        .map(|serial| Serial::from_yaml(serial).unwrap_err())
+        .collect();
+    assert!(errors[0]
+        .message
+        .starts_with("Baud rate 100000 is not supported by the UARTE (1200, 2400,"));
+    assert_eq!(
+        errors[1].message,
+        "Unable to parse parity 'odd' (none or even)"
+    );
+    assert_eq!(
+        errors[2].message,
+        "Unsupported stop bits 2, the UARTE only supports 1"
+    );
+}
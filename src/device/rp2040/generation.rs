@@ -0,0 +1,419 @@
+use std::collections::HashSet;
+
+use quote::format_ident;
+use syn::{parse_quote, parse_str, Ident, Item, ItemStruct, Stmt};
+
+use crate::device::{
+    generation::{
+        fields, frequency_consts, nvic_unmasks, scaled_duty, static_init_and_return,
+        ComponentGroups, Initialized,
+    },
+    used_gpios, InterruptMode, Parity, PeripheralId, PinMode, StopBits,
+};
+
+use super::{gpio_name, pwm_channel, Channel, Pin, Rp2040Peripherals, NVIC_PRIO_BITS, SYSCLK};
+
+/// The type of the gpio in the given mode (e.g. `PushPullOutput`)
+fn gpio_type(pin: Pin, mode: &str) -> String {
+    format!(
+        "rp2040_hal::gpio::Pin<rp2040_hal::gpio::bank0::Gpio{}, rp2040_hal::gpio::{}>",
+        pin.0, mode
+    )
+}
+
+/// The function that brings the gpio into the mode and the mode type
+/// parameter of the pin type
+fn init_function_and_type(mode: PinMode) -> (&'static str, &'static str) {
+    match mode {
+        PinMode::InputFloating => ("into_floating_input", "FloatingInput"),
+        PinMode::InputPullUp => ("into_pull_up_input", "PullUpInput"),
+        PinMode::InputPullDown => ("into_pull_down_input", "PullDownInput"),
+        PinMode::OutputPushPull => ("into_push_pull_output", "PushPullOutput"),
+        PinMode::OutputOpenDrain => panic!("The pads of the rp2040 have no open drain mode"),
+    }
+}
+
+/// The edge interrupts of the gpio, both edges can be enabled at once
+fn edges(mode: InterruptMode) -> &'static [&'static str] {
+    match mode {
+        InterruptMode::None => &[],
+        InterruptMode::Rising => &["EdgeHigh"],
+        InterruptMode::Falling => &["EdgeLow"],
+        InterruptMode::RisingFalling => &["EdgeHigh", "EdgeLow"],
+    }
+}
+
+pub struct DeviceInit {
+    init_block: Vec<syn::Stmt>,
+    peripherals: Ident,
+    clocks: Ident,
+    pins: Ident,
+    pwm_slices: Option<Ident>,
+    gpios: HashSet<Pin>,
+}
+
+impl DeviceInit {
+    /// Starts the clocks with `init_clocks_and_plls` and takes the gpio bank
+    fn new(peripheral_config: &Rp2040Peripherals) -> Self {
+        let peripherals = format_ident!("peripherals");
+        let clocks = format_ident!("clocks");
+        let pins = format_ident!("pins");
+        let xosc = peripheral_config.xosc.0 as u32;
+        let init_block = parse_quote!(
+            use rp2040_hal::prelude::*;
+            use rp2040_hal::fugit::RateExtU32;
+            use rp2040_hal::Clock;
+            let mut #peripherals = rp2040_hal::pac::Peripherals::take().unwrap();
+            let mut watchdog = rp2040_hal::Watchdog::new(#peripherals.WATCHDOG);
+            let #clocks = rp2040_hal::clocks::init_clocks_and_plls(
+                #xosc,
+                #peripherals.XOSC,
+                #peripherals.CLOCKS,
+                #peripherals.PLL_SYS,
+                #peripherals.PLL_USB,
+                &mut #peripherals.RESETS,
+                &mut watchdog,
+            )
+            .ok()
+            .unwrap();
+            let sio = rp2040_hal::Sio::new(#peripherals.SIO);
+            let #pins = rp2040_hal::gpio::Pins::new(
+                #peripherals.IO_BANK0,
+                #peripherals.PADS_BANK0,
+                sio.gpio_bank0,
+                &mut #peripherals.RESETS,
+            );
+        );
+        Self {
+            init_block,
+            peripherals,
+            clocks,
+            pins,
+            pwm_slices: None,
+            gpios: used_gpios(&peripheral_config.components())
+                .into_iter()
+                .collect(),
+        }
+    }
+    fn pwm_slices(&mut self) -> Ident {
+        if self.pwm_slices.is_none() {
+            let peripherals_ident = &self.peripherals;
+            let slices_ident = format_ident!("pwm_slices");
+            self.init_block.push(parse_quote!(
+                let #slices_ident = rp2040_hal::pwm::Slices::new(#peripherals_ident.PWM, &mut #peripherals_ident.RESETS);
+            ));
+            self.pwm_slices = Some(slices_ident);
+        }
+        self.pwm_slices.as_ref().unwrap().clone()
+    }
+    /// Takes the gpio from the pool and returns its field in the bank,
+    /// e.g. `pins.gpio25`
+    fn take_gpio(&mut self, pin: Pin) -> syn::Expr {
+        if !self.gpios.remove(&pin) {
+            panic!("Use of uninitialized gpio");
+        }
+        let pins_ident = &self.pins;
+        let pin_name = format_ident!("{}", gpio_name(pin));
+        parse_quote!(#pins_ident.#pin_name)
+    }
+    /// Takes the gpio from the pool and brings it into the given mode.
+    /// expand: let mut binding = pins.gpioN.into_mode();
+    fn init_gpio(&mut self, pin: Pin, mode: PinMode, binding: &str) -> Initialized {
+        let gpio = self.take_gpio(pin);
+        let ident = format_ident!("{}", binding);
+        let (init_function, mode_type) = init_function_and_type(mode);
+        let init_function = format_ident!("{}", init_function);
+        self.init_block.push(parse_quote!(
+            let mut #ident = #gpio.#init_function();
+        ));
+        Initialized {
+            ty: parse_str(&gpio_type(pin, mode_type)).unwrap(),
+            ident,
+        }
+    }
+    fn inputs(&mut self, peripheral_config: &Rp2040Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for gpio in &peripheral_config.gpio.input {
+            let initialized = self.init_gpio(gpio.pin, gpio.mode, &gpio.binding());
+            let gpio_ident = &initialized.ident;
+            for edge in edges(gpio.interrupt_mode) {
+                let edge = format_ident!("{}", edge);
+                // expand: gpioN.set_interrupt_enabled(Interrupt::EdgeX, true);
+                self.init_block.push(parse_quote!(
+                    #gpio_ident.set_interrupt_enabled(rp2040_hal::gpio::Interrupt::#edge, true);
+                ));
+            }
+            idents.push(initialized);
+        }
+        idents
+    }
+    fn outputs(&mut self, peripheral_config: &Rp2040Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for gpio in &peripheral_config.gpio.output {
+            idents.push(self.init_gpio(gpio.pin, gpio.mode, &gpio.binding()));
+        }
+        idents
+    }
+
+    fn timers(&mut self, peripheral_config: &Rp2040Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        let peripherals_ident = &self.peripherals;
+        for timer in &peripheral_config.timer {
+            let timer_ident = format_ident!("{}", timer.id.lower());
+            // expand: let timer = Timer::new(peripherals.TIMER, &mut peripherals.RESETS);
+            self.init_block.push(parse_quote!(
+                let #timer_ident = rp2040_hal::Timer::new(#peripherals_ident.TIMER, &mut #peripherals_ident.RESETS);
+            ));
+            idents.push(Initialized {
+                ty: parse_quote!(rp2040_hal::Timer),
+                ident: timer_ident,
+            });
+        }
+        idents
+    }
+
+    fn pwms(&mut self, peripheral_config: &Rp2040Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for pwm in &peripheral_config.pwm {
+            let slices_ident = self.pwm_slices();
+            let (divider, top) = pwm.divider().unwrap_or_else(|err| panic!("{}", err));
+            let pwm_ident = format_ident!("{}", pwm.binding());
+            let slice = format_ident!("pwm{}", pwm.slice);
+            // expand:
+            // let mut pwmN = pwm_slices.pwmN;
+            // pwmN.set_div_int(divider);
+            // pwmN.set_top(top);
+            // pwmN.enable();
+            self.init_block.append(&mut parse_quote!(
+                let mut #pwm_ident = #slices_ident.#slice;
+                #pwm_ident.set_div_int(#divider);
+                #pwm_ident.set_top(#top);
+                #pwm_ident.enable();
+            ));
+            for pin in &pwm.pins {
+                let channel =
+                    match pwm_channel(pwm.slice, *pin).unwrap_or_else(|err| panic!("{}", err)) {
+                        Channel::A => format_ident!("channel_a"),
+                        Channel::B => format_ident!("channel_b"),
+                    };
+                let gpio = self.take_gpio(*pin);
+                // expand: pwmN.channel_x.output_to(pins.gpioN);
+                self.init_block.push(parse_quote!(
+                    #pwm_ident.#channel.output_to(#gpio);
+                ));
+                if let Some(duty) = pwm.duty {
+                    let duty = scaled_duty(parse_quote!(#pwm_ident.#channel.get_max_duty()), duty);
+                    // expand: pwmN.channel_x.set_duty(duty);
+                    self.init_block.push(parse_quote!(
+                        #pwm_ident.#channel.set_duty(#duty);
+                    ));
+                }
+            }
+            idents.push(Initialized {
+                ty: parse_str(&format!(
+                    "rp2040_hal::pwm::Slice<rp2040_hal::pwm::Pwm{}, rp2040_hal::pwm::FreeRunning>",
+                    pwm.slice
+                ))
+                .unwrap(),
+                ident: pwm_ident,
+            });
+        }
+        idents
+    }
+
+    fn serials(&mut self, peripheral_config: &Rp2040Peripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for serial in &peripheral_config.serial {
+            let tx = self.take_gpio(serial.tx);
+            let rx = self.take_gpio(serial.rx);
+            let clocks_ident = &self.clocks;
+            let peripherals_ident = &self.peripherals;
+            let serial_ident = format_ident!("{}", serial.binding());
+            let serial_upper = format_ident!("{}", serial.id.upper());
+            let baud = serial.baud_rate.0;
+            let data_bits = format_ident!(
+                "{}",
+                match serial.word_length {
+                    5 => "Five",
+                    6 => "Six",
+                    7 => "Seven",
+                    _ => "Eight",
+                }
+            );
+            let parity: syn::Expr = match serial.parity {
+                Parity::None => parse_quote!(None),
+                Parity::Even => parse_quote!(Some(rp2040_hal::uart::Parity::Even)),
+                Parity::Odd => parse_quote!(Some(rp2040_hal::uart::Parity::Odd)),
+            };
+            let stop_bits = format_ident!(
+                "{}",
+                match serial.stop_bits {
+                    StopBits::Stop1 => "One",
+                    StopBits::Stop2 => "Two",
+                    StopBits::Stop0p5 | StopBits::Stop1p5 =>
+                        panic!("The uart of the rp2040 sends 1 or 2 stop bits"),
+                }
+            );
+            // expand:
+            // let mut uartN = UartPeripheral::new(peripherals.UARTN, (tx, rx), &mut peripherals.RESETS)
+            //     .enable(UartConfig::new(baud.Hz(), ..), clocks.peripheral_clock.freq())
+            //     .unwrap();
+            self.init_block.push(parse_quote!(
+                let mut #serial_ident = rp2040_hal::uart::UartPeripheral::new(
+                    #peripherals_ident.#serial_upper,
+                    (
+                        #tx.into_mode::<rp2040_hal::gpio::FunctionUart>(),
+                        #rx.into_mode::<rp2040_hal::gpio::FunctionUart>(),
+                    ),
+                    &mut #peripherals_ident.RESETS,
+                )
+                .enable(
+                    rp2040_hal::uart::UartConfig::new(
+                        #baud.Hz(),
+                        rp2040_hal::uart::DataBits::#data_bits,
+                        #parity,
+                        rp2040_hal::uart::StopBits::#stop_bits,
+                    ),
+                    #clocks_ident.peripheral_clock.freq(),
+                )
+                .unwrap();
+            ));
+            if serial.interrupt.rx() {
+                self.init_block
+                    .push(parse_quote!(#serial_ident.enable_rx_interrupt();));
+            }
+            if serial.interrupt.tx() {
+                self.init_block
+                    .push(parse_quote!(#serial_ident.enable_tx_interrupt();));
+            }
+            idents.push(Initialized {
+                ty: parse_str(&format!(
+                    "rp2040_hal::uart::UartPeripheral<rp2040_hal::uart::Enabled, rp2040_hal::pac::{}, ({}, {})>",
+                    serial.id.upper(),
+                    gpio_type(serial.tx, "FunctionUart"),
+                    gpio_type(serial.rx, "FunctionUart")
+                ))
+                .unwrap(),
+                ident: serial_ident,
+            });
+        }
+        idents
+    }
+
+    pub(crate) fn get_init_block(
+        peripheral_config: &Rp2040Peripherals,
+        item_struct: &ItemStruct,
+    ) -> (Vec<Stmt>, syn::Type, Vec<Item>) {
+        let mut device_init = DeviceInit::new(peripheral_config);
+        let inputs = device_init.inputs(peripheral_config);
+        let outputs = device_init.outputs(peripheral_config);
+        let timer = device_init.timers(peripheral_config);
+        let pwm = device_init.pwms(peripheral_config);
+        let serial = device_init.serials(peripheral_config);
+        static_init_and_return(
+            device_init.init_block,
+            item_struct,
+            ComponentGroups {
+                inputs: fields(&inputs),
+                outputs: fields(&outputs),
+                timers: fields(&timer),
+                pwm: fields(&pwm),
+                serial: fields(&serial),
+            },
+        )
+    }
+}
+
+/// `init_clocks_and_plls` always configures 125mhz, which also clocks the peripherals
+pub(crate) fn clock_consts() -> Vec<syn::ImplItem> {
+    frequency_consts(&[("SYSCLK", SYSCLK), ("PERIPHERAL_CLOCK", SYSCLK)])
+}
+
+/// Unmasks the interrupts of the gpio bank and of the uarts in the NVIC
+pub(crate) fn interrupt_unmasks(peripheral_config: &Rp2040Peripherals) -> Vec<Stmt> {
+    let interrupts = peripheral_config
+        .interrupts()
+        .unwrap_or_else(|errors| panic!("{}", errors[0]));
+    nvic_unmasks(&parse_quote!(rp2040_hal::pac), &interrupts, NVIC_PRIO_BITS)
+}
+
+#[test]
+fn init_block_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: pico
+gpio:
+  input:
+    - gpio2:
+      mode: pull_up
+      interrupt: rising_falling
+      priority: 1
+  output:
+    - gpio25: push_pull
+      name: led
+pwm:
+  - pwm4:
+    pins:
+      - gpio9
+    freq: 1khz
+    duty: 25%
+serial:
+  - uart0:
+    tx: gpio0
+    rx: gpio1
+    baud: 115200
+    interrupt: rx
+",
+    )
+    .unwrap();
+    let config = crate::device::DeviceConfig::from_yaml(&yaml[0]).unwrap();
+    let item_struct: ItemStruct = parse_quote!(
+        struct Board {}
+    );
+    let (init_block, _, items) = config.get_init_fn(&item_struct);
+    let init_block = quote::quote!(#(#init_block)*).to_string();
+    for expected in [
+        quote::quote!(let mut gpio2 = pins.gpio2.into_pull_up_input();),
+        quote::quote!(gpio2.set_interrupt_enabled(rp2040_hal::gpio::Interrupt::EdgeLow, true);),
+        quote::quote!(let mut led = pins.gpio25.into_push_pull_output();),
+        quote::quote!(pwm4.set_div_int(2u8); pwm4.set_top(62499u16);),
+        quote::quote!(pwm4.channel_b.output_to(pins.gpio9);),
+        quote::quote!(uart0.enable_rx_interrupt();),
+    ] {
+        assert!(
+            init_block.contains(&expected.to_string()),
+            "{} in {}",
+            expected,
+            init_block
+        );
+    }
+    let serial = &items[4];
+    #[rustfmt::skip]
+    let expected: Item = parse_quote!(
+        struct BoardSerial {
+            pub uart0: rp2040_hal::uart::UartPeripheral<
+                rp2040_hal::uart::Enabled,
+                rp2040_hal::pac::UART0,
+                (
+                    rp2040_hal::gpio::Pin<rp2040_hal::gpio::bank0::Gpio0, rp2040_hal::gpio::FunctionUart>,
+                    rp2040_hal::gpio::Pin<rp2040_hal::gpio::bank0::Gpio1, rp2040_hal::gpio::FunctionUart>
+                )
+            >,
+        }
+    );
+    assert_eq!(
+        quote::quote!(#serial).to_string(),
+        quote::quote!(#expected).to_string()
+    );
+    let unmasks = config.get_interrupt_unmasks();
+    assert_eq!(
+        quote::quote!(#(#unmasks)*).to_string(),
+        quote::quote!(
+            let mut nvic = rp2040_hal::pac::CorePeripherals::steal().NVIC;
+            nvic.set_priority(rp2040_hal::pac::Interrupt::IO_IRQ_BANK0, 64u8);
+            rp2040_hal::pac::NVIC::unmask(rp2040_hal::pac::Interrupt::IO_IRQ_BANK0);
+            rp2040_hal::pac::NVIC::unmask(rp2040_hal::pac::Interrupt::UART0_IRQ);
+        )
+        .to_string()
+    );
+}
@@ -0,0 +1,683 @@
+//! The rp2040.
+//! Its gpios form a single bank (gpio0 to gpio29) and each gpio selects
+//! the peripheral it is connected to with its function select. The
+//! function of a gpio is fixed by its number, e.g. gpio0 can be the tx
+//! line of uart0 or channel A of pwm slice 0.
+use std::collections::{BTreeMap, BTreeSet};
+use yaml_rust::Yaml;
+
+mod generation;
+
+use self::generation::DeviceInit;
+
+use super::{
+    check_components, check_unique, clocks_yaml, component_key, merge_interrupts, name_from_yaml,
+    parse_list, pwm_pins_from_yaml, Clocks, Component, Device, Duty, GpioPin, Hertz, InterruptMode,
+    PeripheralId, PinMode, Priority, SerialInterrupt, StopBits, Tolerance, DEFAULT_PWM_FREQUENCY,
+};
+use crate::error::{describe, Error, Location};
+
+#[cfg(test)]
+use super::messages;
+
+type Gpios = super::Gpios<Pin>;
+type Serial = super::Serial<SerialID, Pin>;
+type Timer = super::Timer<TimerID>;
+
+/// Bindings of the generated init function that are not components
+const RESERVED_BINDINGS: &[&str] = &[
+    "peripherals",
+    "watchdog",
+    "clocks",
+    "sio",
+    "pins",
+    "pwm_slices",
+];
+
+/// The rp2040 implements the upper 2 bits of the 8 bit interrupt priority
+const NVIC_PRIO_BITS: u8 = 2;
+
+/// The number of gpios in the user bank
+const GPIOS: usize = 30;
+
+/// The system clock that `init_clocks_and_plls` configures
+const SYSCLK: Hertz = Hertz(125_000_000);
+/// The crystal of the pico board
+const XOSC_DEFAULT: Hertz = Hertz(12_000_000);
+/// The pll reference has to be at least 5mhz, the oscillator runs up to 15mhz
+const XOSC_MIN: Hertz = Hertz(5_000_000);
+const XOSC_MAX: Hertz = Hertz(15_000_000);
+/// The hal divides the vco frequencies of the system and the usb pll
+/// by the crystal
+const PLL_SYS_VCO: usize = 1_500_000_000;
+const PLL_USB_VCO: usize = 480_000_000;
+
+#[derive(Debug)]
+pub struct Rp2040Peripherals {
+    gpio: Gpios,
+    timer: Vec<Timer>,
+    pwm: Vec<PWM>,
+    serial: Vec<Serial>,
+    /// Frequency of the crystal oscillator
+    xosc: Hertz,
+}
+
+impl Device for Rp2040Peripherals {
+    fn kinds() -> &'static [&'static str] {
+        &["rp2040", "pico"]
+    }
+    /// Parses the peripherals and checks them against the hardware constraints.
+    /// The constraints are only checked if all components could be parsed.
+    fn from_yaml(yaml: &Yaml, clocks: Clocks) -> Result<Self, Vec<Error>> {
+        let mut errors = Vec::new();
+        let peripherals = Self {
+            gpio: Gpios::from_yaml(&yaml["gpio"], NVIC_PRIO_BITS, &mut errors),
+            timer: parse_list(&yaml["timer"], Timer::from_yaml, &mut errors),
+            pwm: parse_list(&yaml["pwm"], PWM::from_yaml, &mut errors),
+            serial: parse_list(
+                &yaml["serial"],
+                |serial| Serial::from_yaml(serial, NVIC_PRIO_BITS),
+                &mut errors,
+            ),
+            xosc: clocks.hse.unwrap_or(XOSC_DEFAULT),
+        };
+        if errors.is_empty() {
+            errors = peripherals.check();
+        }
+        for error in check_clocks(clocks) {
+            errors.push(Error::at(clocks_yaml(yaml), error));
+        }
+        if errors.is_empty() {
+            Ok(peripherals)
+        } else {
+            Err(errors)
+        }
+    }
+    fn init_fn(
+        &self,
+        item_struct: &syn::ItemStruct,
+    ) -> (Vec<syn::Stmt>, syn::Type, Vec<syn::Item>) {
+        DeviceInit::get_init_block(self, item_struct)
+    }
+    fn clock_consts(&self) -> Vec<syn::ImplItem> {
+        generation::clock_consts()
+    }
+    fn interrupt_unmasks(&self) -> Vec<syn::Stmt> {
+        generation::interrupt_unmasks(self)
+    }
+}
+
+/// `init_clocks_and_plls` runs the system clock at 125mhz from the crystal,
+/// which is the only clock that can be chosen.
+fn check_clocks(clocks: Clocks) -> Vec<String> {
+    let mut errors = Vec::new();
+    if let Some(hse) = clocks.hse {
+        if hse < XOSC_MIN
+            || hse > XOSC_MAX
+            || !PLL_SYS_VCO.is_multiple_of(hse.0)
+            || !PLL_USB_VCO.is_multiple_of(hse.0)
+        {
+            errors.push(format!(
+                "Clock 'hse' of {} is not supported, the crystal has to be between {} and {} and divide 1500mhz and 480mhz",
+                hse, XOSC_MIN, XOSC_MAX
+            ));
+        }
+    }
+    match clocks.sysclk {
+        Some(sysclk) if sysclk != SYSCLK => errors.push(format!(
+            "Clock 'sysclk' of {} is not supported, the rp2040 is initialized with {}",
+            sysclk, SYSCLK
+        )),
+        _ => {}
+    }
+    let fixed = [
+        ("hclk", clocks.hclk),
+        ("pclk1", clocks.pclk1),
+        ("pclk2", clocks.pclk2),
+        ("adcclk", clocks.adcclk),
+    ];
+    for (name, frequency) in fixed.iter() {
+        if frequency.is_some() {
+            errors.push(format!(
+                "Clock '{}' cannot be configured on the rp2040",
+                name
+            ));
+        }
+    }
+    errors
+}
+
+impl Rp2040Peripherals {
+    /// Checks the pwm slices and uarts against the function select of their
+    /// gpios and the outputs and frames against what the pads and uarts support.
+    fn check(&self) -> Vec<Error> {
+        let mut errors = check_unique(self.timer.iter().map(|timer| {
+            (
+                timer.id,
+                format!("Timer '{}'", timer.id.lower()),
+                timer.location,
+            )
+        }));
+        for gpio in &self.gpio.output {
+            if gpio.mode == PinMode::OutputOpenDrain {
+                errors.push(Error::new(
+                    gpio.location,
+                    format!(
+                        "Gpio '{}' cannot be open drain, the pads of the rp2040 have no open drain mode",
+                        gpio.pin.name()
+                    ),
+                ));
+            }
+        }
+        errors.append(&mut check_unique(self.pwm.iter().map(|pwm| {
+            (
+                pwm.slice,
+                format!("Pwm slice 'pwm{}'", pwm.slice),
+                pwm.location,
+            )
+        })));
+        for pwm in &self.pwm {
+            let mut channels = BTreeSet::new();
+            for pin in &pwm.pins {
+                match pwm_channel(pwm.slice, *pin) {
+                    Ok(channel) if !channels.insert(channel) => errors.push(Error::new(
+                        pwm.location,
+                        format!(
+                            "Channel '{}' of pwm slice 'pwm{}' is used multiple times",
+                            channel.name(),
+                            pwm.slice
+                        ),
+                    )),
+                    Ok(_) => {}
+                    Err(err) => errors.push(Error::new(pwm.location, err)),
+                }
+            }
+            if let Err(err) = pwm.divider() {
+                errors.push(Error::new(pwm.location, err));
+            }
+        }
+        errors.append(&mut check_unique(self.serial.iter().map(|serial| {
+            (
+                serial.id,
+                format!("Serial '{}'", serial.id.lower()),
+                serial.location,
+            )
+        })));
+        for serial in &self.serial {
+            if let Err(err) = serial.check_frame() {
+                errors.push(Error::new(serial.location, err));
+            }
+            for (pin, line) in [(serial.tx, UartLine::Tx), (serial.rx, UartLine::Rx)] {
+                if let Err(err) = uart_function(serial.id, pin, line) {
+                    errors.push(Error::new(serial.location, err));
+                }
+            }
+            if let Err(err) = serial.baud_rate_divisor(SYSCLK) {
+                errors.push(Error::new(serial.location, err));
+            }
+        }
+        errors.append(&mut check_components(RESERVED_BINDINGS, &self.components()));
+        if let Err(mut interrupt_errors) = self.interrupts() {
+            errors.append(&mut interrupt_errors);
+        }
+        errors
+    }
+    /// All nvic interrupts of the configured sources and their priority.
+    /// All inputs share the interrupt of the gpio bank.
+    fn interrupts(&self) -> Result<BTreeMap<String, Option<Priority>>, Vec<Error>> {
+        let gpios = self
+            .gpio
+            .input
+            .iter()
+            .filter(|gpio| gpio.interrupt_mode != InterruptMode::None)
+            .map(|gpio| {
+                (
+                    "IO_IRQ_BANK0".to_string(),
+                    gpio.pin.name(),
+                    gpio.priority,
+                    gpio.location,
+                )
+            });
+        let serials = self
+            .serial
+            .iter()
+            .filter(|serial| serial.interrupt != SerialInterrupt::None)
+            .map(|serial| {
+                (
+                    format!("{}_IRQ", serial.id.upper()),
+                    serial.id.lower().to_string(),
+                    serial.priority,
+                    serial.location,
+                )
+            });
+        merge_interrupts(gpios.chain(serials).collect())
+    }
+    /// The components in the order of the yaml sections
+    fn components(&self) -> [&dyn Component<Pin>; 4] {
+        [&self.gpio, &self.timer, &self.pwm, &self.serial]
+    }
+}
+
+/// The function select of a gpio connects it to one uart line.
+/// The gpios are assigned in groups of four (tx, rx, cts and rts) that
+/// alternate between uart0 and uart1, starting with uart0 on gpio0 to gpio3,
+/// followed by two groups of uart1.
+fn uart_function(id: SerialID, pin: Pin, line: UartLine) -> Result<(), String> {
+    let connects = |pin: Pin| {
+        let uart = match (pin.0 + 4) / 8 % 2 {
+            0 => SerialID::Uart0,
+            _ => SerialID::Uart1,
+        };
+        uart == id && pin.0 % 4 == line as usize
+    };
+    if connects(pin) {
+        return Ok(());
+    }
+    let possible: Vec<String> = (0..GPIOS)
+        .map(Pin)
+        .filter(|pin| connects(*pin))
+        .map(gpio_name)
+        .collect();
+    Err(format!(
+        "Gpio '{}' cannot be the {} gpio of serial '{}' (possible gpios: {})",
+        gpio_name(pin),
+        line.name(),
+        id.lower(),
+        possible.join(", ")
+    ))
+}
+
+/// The channel of the pwm slice on the gpio.
+/// Each slice drives two neighbouring gpios and repeats after gpio15.
+fn pwm_channel(slice: usize, pin: Pin) -> Result<Channel, String> {
+    if pin.0 / 2 % 8 == slice {
+        return Ok(match pin.0 % 2 {
+            0 => Channel::A,
+            _ => Channel::B,
+        });
+    }
+    let possible: Vec<String> = (0..GPIOS)
+        .map(Pin)
+        .filter(|pin| pin.0 / 2 % 8 == slice)
+        .map(gpio_name)
+        .collect();
+    Err(format!(
+        "Gpio '{}' is not a channel of pwm slice 'pwm{}' (possible gpios: {})",
+        gpio_name(pin),
+        slice,
+        possible.join(", ")
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UartLine {
+    Tx = 0,
+    Rx = 1,
+}
+
+impl UartLine {
+    fn name(&self) -> &str {
+        match self {
+            UartLine::Tx => "tx",
+            UartLine::Rx => "rx",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum SerialID {
+    Uart0,
+    Uart1,
+}
+
+impl PeripheralId for SerialID {
+    const EXAMPLE: &'static str = "uart0";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml.as_str().map(str::to_lowercase).as_deref() {
+            Some("uart0") => Ok(SerialID::Uart0),
+            Some("uart1") => Ok(SerialID::Uart1),
+            _ => Err(Error::at(
+                yaml,
+                format!("Unknown serial {} (uart0 or uart1)", describe(yaml)),
+            )),
+        }
+    }
+    fn lower(&self) -> &str {
+        match self {
+            SerialID::Uart0 => "uart0",
+            SerialID::Uart1 => "uart1",
+        }
+    }
+}
+
+impl Serial {
+    /// The uart sends 5 to 8 data bits with 1 or 2 stop bits
+    fn check_frame(&self) -> Result<(), String> {
+        if self.word_length > 8 {
+            return Err(format!(
+                "Serial '{}' supports 5 to 8 data bits, but {} are configured",
+                self.id.lower(),
+                self.word_length
+            ));
+        }
+        match self.stop_bits {
+            StopBits::Stop1 | StopBits::Stop2 => Ok(()),
+            StopBits::Stop0p5 | StopBits::Stop1p5 => Err(format!(
+                "Serial '{}' supports 1 or 2 stop bits",
+                self.id.lower()
+            )),
+        }
+    }
+    /// The uart divides the peripheral clock by 16 times the baud rate with
+    /// a 16 bit integer and a 6 bit fractional part. The hal rounds the
+    /// divisor to the nearest 64th, which has to be within the tolerance of
+    /// the requested baud rate. Returns the divisor in 64ths.
+    fn baud_rate_divisor(&self, peripheral_clock: Hertz) -> Result<u32, String> {
+        let baud = self.baud_rate.0 as u64;
+        let clock = peripheral_clock.0 as u64;
+        let divisor = (8 * clock / baud).div_ceil(2);
+        if !(64..=0xFFFF * 64).contains(&divisor) {
+            return Err(format!(
+                "Baud rate {} of serial '{}' cannot be generated from the peripheral clock of {}",
+                baud,
+                self.id.lower(),
+                peripheral_clock
+            ));
+        }
+        let actual = 4 * clock / divisor;
+        let deviation = Tolerance::deviation(baud, actual);
+        if deviation > self.baud_tolerance {
+            return Err(format!(
+                "Baud rate {} of serial '{}' is generated as {} from the peripheral clock of {}, a deviation of {} exceeds the tolerance of {}",
+                baud,
+                self.id.lower(),
+                actual,
+                peripheral_clock,
+                deviation,
+                self.baud_tolerance
+            ));
+        }
+        Ok(divisor as u32)
+    }
+}
+
+impl Component<Pin> for Serial {
+    fn gpios(&self) -> Vec<(Pin, Location)> {
+        vec![(self.tx, self.location), (self.rx, self.location)]
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        self.whole_bindings()
+    }
+}
+
+impl SerialID {
+    fn upper(&self) -> &str {
+        match self {
+            SerialID::Uart0 => "UART0",
+            SerialID::Uart1 => "UART1",
+        }
+    }
+}
+
+/// A pwm slice with its gpios. The slice drives two channels with
+/// a common frequency.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
+pub struct PWM {
+    slice: usize,
+    /// Binding and field name chosen in the yaml
+    name: Option<String>,
+    pins: Vec<Pin>,
+    frequency: Option<Hertz>,
+    duty: Option<Duty>,
+    location: Location,
+}
+
+impl PWM {
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        let id_yaml = component_key(yaml, "pwm slice", "pwm0")?;
+        let slice = match id_yaml
+            .as_str()
+            .map(str::to_lowercase)
+            .as_deref()
+            .and_then(|id| id.strip_prefix("pwm"))
+            .map(str::parse::<usize>)
+        {
+            Some(Ok(slice @ 0..=7)) => slice,
+            _ => {
+                return Err(Error::at(
+                    id_yaml,
+                    format!("Unknown pwm slice {} (pwm0 to pwm7)", describe(id_yaml)),
+                ))
+            }
+        };
+        Ok(Self {
+            slice,
+            name: name_from_yaml(&yaml["name"])?,
+            pins: pwm_pins_from_yaml(yaml, false)?
+                .into_iter()
+                .map(|(pin, _)| pin)
+                .collect(),
+            frequency: Hertz::from_yaml(&yaml["freq"])?,
+            duty: Duty::from_yaml(&yaml["duty"])?,
+            location: Location::of(yaml),
+        })
+    }
+    /// The name of the generated binding
+    fn binding(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("pwm{}", self.slice))
+    }
+    /// The slice counts from 0 to 'top' with the system clock divided by
+    /// an 8 bit integer divider. Returns the smallest divider and its top
+    /// for the frequency.
+    fn divider(&self) -> Result<(u8, u16), String> {
+        let frequency = self.frequency.unwrap_or(DEFAULT_PWM_FREQUENCY).0;
+        let periods = match frequency {
+            0 => 0,
+            frequency => SYSCLK.0 / frequency,
+        };
+        let divider = periods.div_ceil(0x10000);
+        if periods < 2 || divider > 0xFF {
+            return Err(format!(
+                "Pwm frequency {} of slice 'pwm{}' cannot be generated from the system clock of {}",
+                Hertz(frequency),
+                self.slice,
+                SYSCLK
+            ));
+        }
+        let divider = divider.max(1);
+        Ok((divider as u8, (periods / divider - 1) as u16))
+    }
+}
+
+impl Component<Pin> for PWM {
+    fn gpios(&self) -> Vec<(Pin, Location)> {
+        self.pins.iter().map(|pin| (*pin, self.location)).collect()
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        vec![(
+            self.binding(),
+            format!("pwm slice 'pwm{}'", self.slice),
+            self.name.is_some(),
+            self.location,
+        )]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Channel {
+    A,
+    B,
+}
+
+impl Channel {
+    fn name(&self) -> &str {
+        match self {
+            Channel::A => "A",
+            Channel::B => "B",
+        }
+    }
+}
+
+/// The rp2040 has a single 64 bit microsecond timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum TimerID {
+    Timer,
+}
+
+impl PeripheralId for TimerID {
+    const EXAMPLE: &'static str = "timer";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml.as_str().map(str::to_lowercase).as_deref() {
+            Some("timer") => Ok(TimerID::Timer),
+            _ => Err(Error::at(
+                yaml,
+                format!("Unknown timer {} (timer)", describe(yaml)),
+            )),
+        }
+    }
+    fn lower(&self) -> &str {
+        match self {
+            TimerID::Timer => "timer",
+        }
+    }
+}
+
+/// Gpios are named like 'gpio25' or 'GPIO25'
+impl GpioPin for Pin {
+    const EXAMPLE: &'static str = "gpio0";
+    fn parse(name: &str) -> Result<Self, String> {
+        name.to_lowercase()
+            .strip_prefix("gpio")
+            .and_then(|number| number.parse::<usize>().ok())
+            .map(Pin)
+            .ok_or_else(|| format!("Unable to parse gpio '{}' (e.g. 'gpio0')", name))
+    }
+    fn name(&self) -> String {
+        gpio_name(*self)
+    }
+    fn check_exists(&self) -> Result<(), String> {
+        if self.0 >= GPIOS {
+            Err(format!(
+                "Gpio '{}' does not exist, the gpios are numbered from 0 to {}",
+                self.name(),
+                GPIOS - 1
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The name of the gpio in the datasheet and in the hal
+fn gpio_name(pin: Pin) -> String {
+    format!("gpio{}", pin.0)
+}
+
+/// A gpio of the user bank
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pin(usize);
+
+#[test]
+fn function_test() {
+    assert_eq!(
+        uart_function(SerialID::Uart0, Pin(16), UartLine::Tx),
+        Ok(())
+    );
+    assert_eq!(uart_function(SerialID::Uart1, Pin(9), UartLine::Rx), Ok(()));
+    assert_eq!(
+        uart_function(SerialID::Uart0, Pin(4), UartLine::Tx),
+        Err("Gpio 'gpio4' cannot be the tx gpio of serial 'uart0' (possible gpios: gpio0, gpio12, gpio16, gpio28)".to_string())
+    );
+    assert_eq!(pwm_channel(4, Pin(25)), Ok(Channel::B));
+    assert_eq!(
+        pwm_channel(7, Pin(16)),
+        Err(
+            "Gpio 'gpio16' is not a channel of pwm slice 'pwm7' (possible gpios: gpio14, gpio15)"
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn check_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: rp2040
+clocks:
+  hse: 16mhz
+  sysclk: 133mhz
+gpio:
+  input:
+    - gpio2:
+      mode: pull_up
+      interrupt: falling
+      priority: 1
+    - gpio3:
+      mode: pull_up
+      interrupt: rising
+  output:
+    - gpio30: push_pull
+    - gpio26: open_drain
+pwm:
+  - pwm4:
+    pins:
+      - gpio8
+      - gpio24
+    freq: 1hz
+serial:
+  - uart0:
+    tx: gpio0
+    rx: gpio5
+    baud: 115200
+    stop_bits: 1.5
+    word_length: 9
+",
+    )
+    .unwrap();
+    let clocks = Clocks::from_yaml(&yaml[0]).unwrap();
+    let message = messages(Rp2040Peripherals::from_yaml(&yaml[0], clocks).unwrap_err());
+    assert!(message.contains("Gpio 'gpio30' does not exist"));
+    assert!(message.contains("Channel 'A' of pwm slice 'pwm4' is used multiple times"));
+    assert!(message.contains("Pwm frequency 1hz of slice 'pwm4' cannot be generated"));
+    assert!(message.contains("Gpio 'gpio5' cannot be the rx gpio of serial 'uart0'"));
+    assert!(message.contains("Gpio 'gpio26' cannot be open drain"));
+    assert!(message.contains("Serial 'uart0' supports 5 to 8 data bits, but 9 are configured"));
+    assert!(message.contains("Clock 'hse' of 16mhz is not supported"));
+    assert!(message.contains("Clock 'sysclk' of 133mhz is not supported"));
+    // divides 1500mhz but not the 480mhz of the usb pll
+    let crystal = |hse| Clocks {
+        hse: Some(Hertz(hse)),
+        ..Clocks::default()
+    };
+    assert_eq!(check_clocks(crystal(12_500_000)).len(), 1);
+    assert!(check_clocks(crystal(12_000_000)).is_empty());
+}
+
+#[test]
+fn divider_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+- pwm0:
+  pins: [gpio0]
+  freq: 1khz
+- pwm0:
+  pins: [gpio0]
+  freq: 25khz
+- uart0:
+  tx: gpio0
+  rx: gpio1
+  baud: 115200
+",
+    )
+    .unwrap();
+    let slow = PWM::from_yaml(&yaml[0][0]).unwrap();
+    assert_eq!(slow.divider(), Ok((2, 62499)));
+    let fast = PWM::from_yaml(&yaml[0][1]).unwrap();
+    assert_eq!(fast.divider(), Ok((1, 4999)));
+    let serial = Serial::from_yaml(&yaml[0][2], NVIC_PRIO_BITS).unwrap();
+    // 125mhz / (16 * 115200) = 67.817, rounded to 67 + 52/64
+    assert_eq!(serial.baud_rate_divisor(SYSCLK), Ok(67 * 64 + 52));
+}
@@ -0,0 +1,79 @@
+//! Generation that is shared by the stm32 backends
+use quote::format_ident;
+use syn::{parse_quote, Ident, Stmt};
+
+use super::{GpioPort, Pin};
+use crate::device::{generation::scaled_duty, Duty, PinMode};
+
+/// The function of the hal that brings the gpio into the mode
+pub(in crate::device) fn init_function_name(mode: PinMode) -> &'static str {
+    match mode {
+        PinMode::InputFloating => "into_floating_input",
+        PinMode::InputPullUp => "into_pull_up_input",
+        PinMode::InputPullDown => "into_pull_down_input",
+        PinMode::OutputPushPull => "into_push_pull_output",
+        PinMode::OutputOpenDrain => "into_open_drain_output",
+    }
+}
+
+/// The field of the gpio in the split port (e.g. `pa0`)
+pub(in crate::device) fn gpio_ident<P: GpioPort>(pin: Pin, port: P) -> Ident {
+    format_ident!("p{}{}", port.short(), pin.0)
+}
+
+/// The type of the gpio in the module of its port (e.g. `PA0`)
+pub(in crate::device) fn gpio_short_type<P: GpioPort>(pin: Pin, port: P) -> Ident {
+    format_ident!("P{}{}", port.short().to_uppercase().to_string(), pin.0)
+}
+
+/// Routes the external interrupt line to the port of the input and enables
+/// the interrupt on the edge. The families differ in the peripheral that
+/// routes the line (AFIO or SYSCFG) and in how the EXTI is borrowed.
+/// expand:
+/// pxy.make_interrupt_source(&mut router);
+/// pxy.trigger_on_edge(exti, Edge::EDGE_TYPE);
+/// pxy.enable_interrupt(exti);
+pub(in crate::device) fn interrupt_source(
+    gpio: &Ident,
+    router: &Ident,
+    exti: &syn::Expr,
+    edge: &syn::Path,
+) -> Vec<Stmt> {
+    parse_quote!(
+        #gpio.make_interrupt_source(&mut #router);
+        #gpio.trigger_on_edge(#exti, #edge);
+        #gpio.enable_interrupt(#exti);
+    )
+}
+
+/// The pins that are passed to the pwm of a timer and the pattern that binds
+/// its channels. A single pin is not passed as tuple and does not split into one.
+pub(in crate::device) fn pwm_pins(pin_idents: &[Ident]) -> (syn::Expr, syn::Pat) {
+    match pin_idents {
+        [single] => (parse_quote!(#single), parse_quote!(mut #single)),
+        multiple => (
+            parse_quote!((#(#multiple),*)),
+            parse_quote!((#(mut #multiple),*)),
+        ),
+    }
+}
+
+/// Sets the initial duty cycle of the pwm channels and enables them
+/// expand:
+/// pxy.set_duty(duty);
+/// pxy.enable();
+pub(in crate::device) fn initial_duty(pin_idents: &[Ident], duty: Option<Duty>) -> Vec<Stmt> {
+    let duty = match duty {
+        Some(duty) => duty,
+        None => return Vec::new(),
+    };
+    let mut statements = Vec::new();
+    for ident in pin_idents {
+        let duty = scaled_duty(parse_quote!(#ident.get_max_duty()), duty);
+        statements.append(&mut parse_quote!(
+            #ident.set_duty(#duty);
+            #ident.enable();
+        ));
+    }
+    statements
+}
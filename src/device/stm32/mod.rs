@@ -0,0 +1,370 @@
+//! The parts of the stm32 backends that do not depend on the family:
+//! the gpios of the lettered ports, the external interrupt lines,
+//! the timer channels and the pwm component.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+};
+use yaml_rust::Yaml;
+
+pub(super) mod generation;
+
+use super::{
+    check_unique, component_key, merge_interrupts, pwm_pins_from_yaml, Component, Duty, GpioPin,
+    Hertz, InterruptMode, PeripheralId, Priority, SerialInterrupt,
+};
+use crate::error::{Error, Location};
+
+type Gpio<P> = super::Gpio<(Pin, P)>;
+type Serial<S, P> = super::Serial<S, (Pin, P)>;
+type Timer<T> = super::Timer<T>;
+
+/// The stm32 implement the upper 4 bits of the 8 bit interrupt priority
+pub(super) const NVIC_PRIO_BITS: u8 = 4;
+
+/// The number of a gpio in its port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pin(pub(super) usize);
+
+/// The gpio ports of a family, which are named by a letter
+pub(super) trait GpioPort: Debug + Clone + Copy + Ord {
+    /// The letters of the ports in the messages (e.g. 'a to e')
+    const LETTERS: &'static str;
+    fn from_short(short: char) -> Option<Self>;
+    /// The letter of the port
+    fn short(&self) -> char;
+    /// The pins of most ports are numbered from 0 to 15
+    fn max_pin(&self) -> usize {
+        15
+    }
+    /// The name of the port in the hal (e.g. 'gpioa')
+    fn lower(&self) -> String {
+        format!("gpio{}", self.short())
+    }
+    /// The name of the port in the pac (e.g. 'GPIOA')
+    fn upper(&self) -> String {
+        self.lower().to_uppercase()
+    }
+}
+
+pub(super) fn gpio_name<P: GpioPort>(pin: Pin, port: P) -> String {
+    format!("p{}{}", port.short(), pin.0)
+}
+
+impl<P: GpioPort> GpioPin for (Pin, P) {
+    const EXAMPLE: &'static str = "pa0";
+    fn parse(name: &str) -> Result<Self, String> {
+        let string = name.to_lowercase();
+        let string = string.strip_prefix('p').unwrap_or(&string);
+        let mut chars = string.chars();
+        let port = chars.next().and_then(P::from_short).ok_or_else(|| {
+            format!(
+                "Unable to parse the port of gpio '{}' ({})",
+                name,
+                P::LETTERS
+            )
+        })?;
+        let pin = chars
+            .as_str()
+            .parse::<usize>()
+            .map_err(|_| format!("Unable to parse the pin number of gpio '{}'", name))?;
+        Ok((Pin(pin), port))
+    }
+    fn name(&self) -> String {
+        gpio_name(self.0, self.1)
+    }
+    fn check_exists(&self) -> Result<(), String> {
+        let (pin, port) = self;
+        if pin.0 > port.max_pin() {
+            Err(format!(
+                "Gpio '{}' does not exist, the pins of port {} are numbered from 0 to {}",
+                self.name(),
+                port.short(),
+                port.max_pin()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A timer or serial with its names in the hal
+pub(super) trait Peripheral: PeripheralId + Ord + Copy {
+    /// The name of the peripheral in the pac (e.g. 'TIM2')
+    fn upper(&self) -> &str;
+    /// The bus that clocks the peripheral (e.g. 'apb1')
+    fn peripheral_bus(&self) -> &str;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) enum Channel {
+    C1,
+    C2,
+    C3,
+    C4,
+}
+
+impl Channel {
+    pub fn name(&self) -> &str {
+        match self {
+            Channel::C1 => "C1",
+            Channel::C2 => "C2",
+            Channel::C3 => "C3",
+            Channel::C4 => "C4",
+        }
+    }
+}
+
+/// One of the 16 external interrupt lines that are shared by the gpio ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct ExtiLine(usize);
+
+impl ExtiLine {
+    /// The lines 5 to 9 and 10 to 15 are combined into one interrupt each
+    fn interrupt(&self) -> &str {
+        match self.0 {
+            0 => "EXTI0",
+            1 => "EXTI1",
+            2 => "EXTI2",
+            3 => "EXTI3",
+            4 => "EXTI4",
+            5..=9 => "EXTI9_5",
+            _ => "EXTI15_10",
+        }
+    }
+}
+
+impl<P: GpioPort> Gpio<P> {
+    /// The external interrupt line claimed by the input, none if it does not
+    /// interrupt or its gpio does not exist (reported by `check_components`)
+    fn exti_line(&self) -> Option<ExtiLine> {
+        match (self.interrupt_mode, self.pin.0 .0) {
+            (InterruptMode::None, _) => None,
+            (_, line @ 0..=15) => Some(ExtiLine(line)),
+            _ => None,
+        }
+    }
+}
+
+/// Maps each claimed external interrupt line to the interrupting input.
+/// All ports share the same line for a pin number (e.g. pa3 and pc3 both
+/// use EXTI3), so a line can only be claimed by one input.
+pub(super) fn exti_lines<P: GpioPort>(
+    inputs: &[Gpio<P>],
+) -> Result<BTreeMap<ExtiLine, &Gpio<P>>, Vec<Error>> {
+    let mut errors = Vec::new();
+    let mut exti_lines: BTreeMap<ExtiLine, &Gpio<P>> = BTreeMap::new();
+    for gpio in inputs {
+        let line = match gpio.exti_line() {
+            Some(line) => line,
+            None => continue,
+        };
+        match exti_lines.get(&line) {
+            // the same gpio twice is reported as gpio collision
+            Some(other) if other.pin == gpio.pin => {}
+            Some(other) => errors.push(Error::new(
+                gpio.location,
+                format!(
+                    "Gpios '{}' and '{}' both use interrupt line EXTI{}",
+                    other.pin.name(),
+                    gpio.pin.name(),
+                    line.0
+                ),
+            )),
+            None => {
+                exti_lines.insert(line, gpio);
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(exti_lines)
+    } else {
+        Err(errors)
+    }
+}
+
+/// All nvic interrupts of the interrupting inputs and serials and their
+/// priority. Sources that share an interrupt have to agree on the priority.
+pub(super) fn interrupts<S: Peripheral, P: GpioPort>(
+    inputs: &[Gpio<P>],
+    serials: &[Serial<S, P>],
+) -> Result<BTreeMap<String, Option<Priority>>, Vec<Error>> {
+    let mut errors = Vec::new();
+    if let Err(mut collisions) = exti_lines(inputs) {
+        errors.append(&mut collisions);
+    }
+    let gpios = inputs.iter().filter_map(|gpio| {
+        Some((
+            gpio.exti_line()?.interrupt().to_string(),
+            gpio.pin.name(),
+            gpio.priority,
+            gpio.location,
+        ))
+    });
+    let serials = serials
+        .iter()
+        .filter(|serial| serial.interrupt != SerialInterrupt::None)
+        .map(|serial| {
+            (
+                serial.id.upper().to_string(),
+                serial.id.lower().to_string(),
+                serial.priority,
+                serial.location,
+            )
+        });
+    match merge_interrupts(gpios.chain(serials).collect()) {
+        Ok(interrupts) if errors.is_empty() => Ok(interrupts),
+        Ok(_) => Err(errors),
+        Err(mut conflicts) => {
+            errors.append(&mut conflicts);
+            Err(errors)
+        }
+    }
+}
+
+/// The checks of the timers, pwms and serials that do not depend on the
+/// family: a timer can be captured only once, either as plain timer or for
+/// pwm, a serial can be used once and needs a frame that the usart supports
+/// and a pwm frequency has to be reachable from the clock of its timer.
+pub(super) fn check_peripherals<T: Peripheral, S: Peripheral, P: GpioPort>(
+    timers: &[Timer<T>],
+    pwms: &[PWM<T, P>],
+    serials: &[Serial<S, P>],
+    timer_clock: impl Fn(&str) -> Hertz,
+) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let mut captured = BTreeMap::new();
+    let timer_uses = timers
+        .iter()
+        .map(|timer| (timer, "timer"))
+        .chain(pwms.iter().map(|pwm| (&pwm.timer, "pwm")));
+    for (timer, section) in timer_uses {
+        if let Some(other_section) = captured.insert(timer.id, section) {
+            errors.push(Error::new(
+                timer.location,
+                format!(
+                    "Timer '{}' is used in '{}' and in '{}'",
+                    timer.id.lower(),
+                    other_section,
+                    section
+                ),
+            ));
+        }
+    }
+    errors.append(&mut check_unique(serials.iter().map(|serial| {
+        (
+            serial.id,
+            format!("Serial '{}'", serial.id.lower()),
+            serial.location,
+        )
+    })));
+    for serial in serials {
+        if let Err(err) = serial.frame_length() {
+            errors.push(Error::new(serial.location, err));
+        }
+    }
+    for pwm in pwms {
+        if let Some(frequency) = pwm.frequency {
+            let timer_clock = timer_clock(pwm.timer.id.peripheral_bus());
+            if frequency.0 == 0 || frequency > timer_clock {
+                errors.push(Error::new(
+                    pwm.location,
+                    format!(
+                        "Pwm frequency {} of timer '{}' is not between 1hz and the timer clock of {}",
+                        frequency,
+                        pwm.timer.id.lower(),
+                        timer_clock
+                    ),
+                ));
+            }
+        }
+    }
+    errors
+}
+
+impl<I: PeripheralId, P: GpioPort> Component<(Pin, P)> for Serial<I, P> {
+    fn gpios(&self) -> Vec<((Pin, P), Location)> {
+        vec![(self.tx, self.location), (self.rx, self.location)]
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        self.split_bindings()
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
+pub struct PWM<T, P> {
+    pub(super) timer: Timer<T>,
+    pub(super) pins: Vec<(Pin, P)>,
+    /// Binding and field names of the channels chosen in the yaml
+    names: BTreeMap<(Pin, P), String>,
+    pub(super) frequency: Option<Hertz>,
+    pub(super) duty: Option<Duty>,
+    pub(super) location: Location,
+}
+
+impl<T: PeripheralId, P: GpioPort> PWM<T, P> {
+    /// The name of the generated binding for the channel on the pin
+    pub(super) fn binding(&self, pin: Pin, port: P) -> String {
+        self.names
+            .get(&(pin, port))
+            .cloned()
+            .unwrap_or_else(|| gpio_name(pin, port))
+    }
+    pub(super) fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        let timer = Timer::from_yaml(component_key(yaml, "pwm timer", "tim2")?)?;
+        let mut pins = Vec::new();
+        let mut names = BTreeMap::new();
+        for (pin, name) in pwm_pins_from_yaml(yaml, true)? {
+            if let Some(name) = name {
+                names.insert(pin, name);
+            }
+            pins.push(pin);
+        }
+        Ok(Self {
+            timer,
+            pins,
+            names,
+            frequency: Hertz::from_yaml(&yaml["freq"])?,
+            duty: Duty::from_yaml(&yaml["duty"])?,
+            location: Location::of(yaml),
+        })
+    }
+    /// Reports the channels of the timer that are taken by more than one pin
+    pub(super) fn check_channels(&self, channels: impl IntoIterator<Item = Channel>) -> Vec<Error> {
+        let mut used_channels = BTreeSet::new();
+        channels
+            .into_iter()
+            .filter(|channel| !used_channels.insert(*channel))
+            .map(|channel| {
+                Error::new(
+                    self.location,
+                    format!(
+                        "Channel '{}' of timer '{}' is used multiple times",
+                        channel.name(),
+                        self.timer.id.lower()
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+impl<T: PeripheralId, P: GpioPort> Component<(Pin, P)> for PWM<T, P> {
+    fn gpios(&self) -> Vec<((Pin, P), Location)> {
+        self.pins.iter().map(|pin| (*pin, self.location)).collect()
+    }
+    fn bindings(&self) -> Vec<(String, String, bool, Location)> {
+        self.pins
+            .iter()
+            .map(|(pin, port)| {
+                (
+                    self.binding(*pin, *port),
+                    format!("pwm gpio '{}'", gpio_name(*pin, *port)),
+                    self.names.contains_key(&(*pin, *port)),
+                    self.location,
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,205 @@
+//! The clock tree of the stm32f1 (see reference manual RM0008, section 7.2).
+//! The hal derives the pll multiplier and the bus prescalers from the
+//! requested frequencies in `CFGR::freeze` and only asserts the hardware
+//! limits at runtime. The same derivation is done here at expansion time
+//! so that impossible configurations are rejected by the macro.
+use crate::device::{Clocks, Hertz};
+
+/// Frequency of the internal oscillator
+const HSI: Hertz = Hertz(8_000_000);
+const HSE_MIN: Hertz = Hertz(4_000_000);
+const HSE_MAX: Hertz = Hertz(16_000_000);
+const SYSCLK_MAX: Hertz = Hertz(72_000_000);
+const HCLK_MAX: Hertz = Hertz(72_000_000);
+const PCLK1_MAX: Hertz = Hertz(36_000_000);
+const PCLK2_MAX: Hertz = Hertz(72_000_000);
+const ADCCLK_MAX: Hertz = Hertz(14_000_000);
+
+/// The frequencies the hal configures for the requested clocks
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(super) struct ClockTree {
+    /// The frequencies from the yaml, the hal derives the tree from them
+    pub requested: Clocks,
+    pub sysclk: Hertz,
+    pub hclk: Hertz,
+    pub pclk1: Hertz,
+    pub pclk2: Hertz,
+    pub adcclk: Hertz,
+    ppre1: usize,
+    ppre2: usize,
+    /// Wait states of the flash for the system clock
+    pub flash_latency: u8,
+}
+
+impl ClockTree {
+    /// Mirrors the prescaler selection of `stm32f1xx_hal::rcc::CFGR::freeze`.
+    /// The hal rounds down to the next reachable frequency, unreachable
+    /// requests are reported by [`ClockTree::check`].
+    pub fn solve(requested: Clocks) -> Self {
+        let pllsrcclk = requested.hse.unwrap_or(Hertz(HSI.0 / 2));
+        let pllmul = requested.sysclk.unwrap_or(pllsrcclk).0 / pllsrcclk.0;
+        let sysclk = if pllmul == 1 {
+            requested.hse.unwrap_or(HSI)
+        } else {
+            Hertz(pllsrcclk.0 * pllmul.clamp(2, 16))
+        };
+
+        let hpre = match requested.hclk.map(|hclk| sysclk.0 / hclk.0) {
+            None | Some(0..=1) => 1,
+            Some(2) => 2,
+            Some(3..=5) => 4,
+            Some(6..=11) => 8,
+            Some(12..=39) => 16,
+            Some(40..=95) => 64,
+            Some(96..=191) => 128,
+            Some(192..=383) => 256,
+            Some(_) => 512,
+        };
+        let hclk = Hertz(sysclk.0 / hpre);
+
+        let apb_prescaler = |pclk: Option<Hertz>| match pclk.map(|pclk| hclk.0 / pclk.0) {
+            None | Some(0..=1) => 1,
+            Some(2) => 2,
+            Some(3..=5) => 4,
+            Some(6..=11) => 8,
+            Some(_) => 16,
+        };
+        let ppre1 = apb_prescaler(requested.pclk1);
+        let ppre2 = apb_prescaler(requested.pclk2);
+        let pclk1 = Hertz(hclk.0 / ppre1);
+        let pclk2 = Hertz(hclk.0 / ppre2);
+
+        let adcpre = match requested.adcclk.map(|adcclk| pclk2.0 / adcclk.0) {
+            Some(0..=2) => 2,
+            Some(3..=4) => 4,
+            Some(5..=7) => 6,
+            None | Some(_) => 8,
+        };
+        let adcclk = Hertz(pclk2.0 / adcpre);
+
+        let flash_latency = if sysclk.0 <= 24_000_000 {
+            0
+        } else if sysclk.0 <= 48_000_000 {
+            1
+        } else {
+            2
+        };
+
+        Self {
+            requested,
+            sysclk,
+            hclk,
+            pclk1,
+            pclk2,
+            adcclk,
+            ppre1,
+            ppre2,
+            flash_latency,
+        }
+    }
+    /// Reports requested frequencies that the hal cannot configure and
+    /// derived frequencies that exceed the hardware limits.
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(hse) = self.requested.hse {
+            if hse < HSE_MIN || hse > HSE_MAX {
+                errors.push(format!(
+                    "Clock 'hse' of {} is not between {} and {}",
+                    hse, HSE_MIN, HSE_MAX
+                ));
+            }
+        }
+        let clocks = [
+            ("sysclk", self.requested.sysclk, self.sysclk, SYSCLK_MAX),
+            ("hclk", self.requested.hclk, self.hclk, HCLK_MAX),
+            ("pclk1", self.requested.pclk1, self.pclk1, PCLK1_MAX),
+            ("pclk2", self.requested.pclk2, self.pclk2, PCLK2_MAX),
+            ("adcclk", self.requested.adcclk, self.adcclk, ADCCLK_MAX),
+        ];
+        for (name, requested, achieved, max) in clocks.iter() {
+            match requested {
+                Some(requested) if requested > max => errors.push(format!(
+                    "Clock '{}' of {} exceeds the maximum of {}",
+                    name, requested, max
+                )),
+                Some(requested) if achieved > requested => errors.push(format!(
+                    "Clock '{}' of {} cannot be reached, the lowest possible frequency is {}",
+                    name, requested, achieved
+                )),
+                None if achieved > max => errors.push(format!(
+                    "Clock '{}' defaults to {} which exceeds the maximum of {}, set it explicitly",
+                    name, achieved, max
+                )),
+                _ => {}
+            }
+        }
+        errors
+    }
+    /// The frequency of the peripheral bus ("apb1" or "apb2")
+    pub fn bus_clock(&self, bus: &str) -> Hertz {
+        match bus {
+            "apb1" => self.pclk1,
+            "apb2" => self.pclk2,
+            other => unreachable!("unknown peripheral bus {}", other),
+        }
+    }
+    /// The timers run with twice the bus frequency if the bus is prescaled
+    pub fn timer_clock(&self, bus: &str) -> Hertz {
+        let prescaler = match bus {
+            "apb1" => self.ppre1,
+            "apb2" => self.ppre2,
+            other => unreachable!("unknown peripheral bus {}", other),
+        };
+        match prescaler {
+            1 => self.bus_clock(bus),
+            _ => Hertz(self.bus_clock(bus).0 * 2),
+        }
+    }
+}
+
+#[test]
+fn clock_tree_test() {
+    let tree = ClockTree::solve(Clocks {
+        hse: Some(Hertz(8_000_000)),
+        sysclk: Some(Hertz(72_000_000)),
+        pclk1: Some(Hertz(36_000_000)),
+        adcclk: Some(Hertz(12_000_000)),
+        ..Clocks::default()
+    });
+    assert!(tree.check().is_empty());
+    assert_eq!(tree.hclk, Hertz(72_000_000));
+    assert_eq!(tree.pclk1, Hertz(36_000_000));
+    assert_eq!(tree.pclk2, Hertz(72_000_000));
+    assert_eq!(tree.adcclk, Hertz(12_000_000));
+    assert_eq!(tree.timer_clock("apb1"), Hertz(72_000_000));
+    assert_eq!(tree.flash_latency, 2);
+
+    // the internal oscillator reaches at most 64mhz over the pll
+    let tree = ClockTree::solve(Clocks {
+        sysclk: Some(Hertz(70_000_000)),
+        pclk1: Some(Hertz(32_000_000)),
+        ..Clocks::default()
+    });
+    assert!(tree.check().is_empty());
+    assert_eq!(tree.sysclk, Hertz(64_000_000));
+
+    let tree = ClockTree::solve(Clocks {
+        sysclk: Some(Hertz(100_000_000)),
+        ..Clocks::default()
+    });
+    assert_eq!(
+        tree.check(),
+        vec![
+            "Clock 'sysclk' of 100mhz exceeds the maximum of 72mhz",
+            "Clock 'pclk1' defaults to 64mhz which exceeds the maximum of 36mhz, set it explicitly",
+        ]
+    );
+    let tree = ClockTree::solve(Clocks {
+        sysclk: Some(Hertz(1_000_000)),
+        ..Clocks::default()
+    });
+    assert_eq!(
+        tree.check(),
+        vec!["Clock 'sysclk' of 1mhz cannot be reached, the lowest possible frequency is 8mhz"]
+    );
+}
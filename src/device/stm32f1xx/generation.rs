@@ -1,54 +1,64 @@
 use std::{
-    borrow::BorrowMut, cell::RefCell, collections::HashSet, fmt::format, ops::Deref, rc::Rc,
+    cell::RefCell,
+    collections::{BTreeSet, HashSet},
 };
 
 use quote::format_ident;
-use syn::{parse_quote, parse_str, Ident, Stmt};
+use syn::{parse_quote, parse_str, Ident, Item, ItemStruct, Stmt};
 
 use crate::device::{
-    stm32f1xx::{InterruptMode, PinMode},
-    DeviceConfig, Hertz,
+    generation::{
+        fields, frequency_consts, nvic_unmasks, static_init_and_return, ComponentGroups,
+        Initialized,
+    },
+    stm32::generation::{
+        gpio_ident, gpio_short_type, init_function_name, initial_duty, interrupt_source, pwm_pins,
+    },
+    used_gpios, Clocks, FrameLength, InterruptMode, PeripheralId, PinMode, DEFAULT_PWM_FREQUENCY,
 };
 
-use super::{Gpio, Pin, Port, Stm32f1xxPeripherals};
+use super::{
+    remap, Channel, GpioPort, Peripheral, Pin, Port, Stm32f1xxPeripherals, NVIC_PRIO_BITS,
+};
 
-pub trait InitializedComponent {
-    fn ty(&self) -> syn::Type;
-    fn identifier(&self) -> Ident;
+fn gpio_type(pin: Pin, port: Port, mode: PinMode) -> syn::Type {
+    let (direction, mode) = match mode {
+        PinMode::InputFloating => ("Input", "Floating"),
+        PinMode::InputPullUp => ("Input", "PullUp"),
+        PinMode::InputPullDown => ("Input", "PullDown"),
+        PinMode::OutputPushPull => ("Output", "PushPull"),
+        PinMode::OutputOpenDrain => ("Output", "OpenDrain"),
+    };
+    parse_str(&format!(
+        "stm32f1xx_hal::gpio::{}::{}<stm32f1xx_hal::gpio::{}<stm32f1xx_hal::gpio::{}>>",
+        port.lower(),
+        gpio_short_type(pin, port),
+        direction,
+        mode
+    ))
+    .unwrap()
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
-pub struct InitializedGpio {
-    pin: Pin,
-    port: Port,
-    mode: PinMode,
-    id: Ident,
-}
+/// The alternate function of the timer and serial outputs
+const ALTERNATE_PUSH_PULL: &str = "into_alternate_push_pull";
 
-impl InitializedComponent for InitializedGpio {
-    fn ty(&self) -> syn::Type {
-        let port_name = self.port.lower();
-        let pin_type = gpio_short_type(self.pin, self.port);
-        let direction = self.mode.direction_name();
-        let mode = self.mode.mode_name();
-        parse_str(&format!(
-            "stm32f1xx_hal::gpio::{}::{}<stm32f1xx_hal::gpio::{}<stm32f1xx_hal::gpio::{}>>",
-            port_name, pin_type, direction, mode
-        ))
-        .unwrap()
-    }
-    fn identifier(&self) -> Ident {
-        self.id.clone()
+/// The variant of `Edge` in the hal
+fn edge_name(mode: InterruptMode) -> &'static str {
+    match mode {
+        InterruptMode::None => {
+            panic!("InterruptMode::None cannot be converted into an identifier")
+        }
+        InterruptMode::Rising => "RISING",
+        InterruptMode::Falling => "FALLING",
+        InterruptMode::RisingFalling => "RISING_FALLING",
     }
 }
 
-// FIXME: delete
-impl InitializedComponent for () {
-    fn ty(&self) -> syn::Type {
-        panic!("used '()' as component")
-    }
-    fn identifier(&self) -> Ident {
-        panic!("used '()' as component")
+/// The function of the serial config that sets the frame length
+fn word_length_function_name(frame_length: FrameLength) -> &'static str {
+    match frame_length {
+        FrameLength::Bits8 => "wordlength_8bits",
+        FrameLength::Bits9 => "wordlength_9bits",
     }
 }
 
@@ -59,15 +69,17 @@ pub struct DeviceInit {
     rcc: Option<Ident>, // FIXME: should be a state machine to reflect consumption on 'freeze(self, ...)'
     cfgr: Option<Ident>,
     afio: Option<Ident>,
-    clocks: Option<(Ident, Hertz)>,
+    clocks: Option<(Ident, Clocks)>,
     gpios: Option<RefCell<HashSet<Ident>>>,
 }
 
-fn gpio_ident(pin: Pin, port: Port) -> Ident {
-    format_ident!("p{}{}", port.short(), pin.0)
-}
-fn gpio_short_type(pin: Pin, port: Port) -> Ident {
-    format_ident!("P{}{}", port.short().to_uppercase().to_string(), pin.0)
+/// The configuration register of the port that holds the mode of the pin
+fn control_reg(pin: Pin) -> &'static str {
+    if (pin.0 % 16) < 8 {
+        "crl"
+    } else {
+        "crh"
+    }
 }
 
 impl DeviceInit {
@@ -82,8 +94,8 @@ impl DeviceInit {
         );
         Self {
             init_block,
-            peripherals: peripherals,
-            flash: flash,
+            peripherals,
+            flash,
             rcc: None,
             cfgr: None,
             afio: None,
@@ -127,28 +139,43 @@ impl DeviceInit {
         }
         self.afio.as_ref().unwrap().clone()
     }
-    fn clocks(&mut self, clock: Hertz) -> Ident {
+    fn clocks(&mut self, clocks: Clocks) -> Ident {
         if self.clocks.is_none() {
-            let freq = clock.0 as u32;
             let cfgr_ident = self.cfgr();
             let flash_ident = &self.flash;
             let clocks_ident = format_ident!("clocks");
-            self.init_block.append(&mut parse_quote!(
-                let #cfgr_ident = #cfgr_ident.sysclk(#freq.hz());
-                let #clocks_ident = #cfgr_ident.freeze(&mut #flash_ident.acr);
+            // expand: let clocks = cfgr.use_hse(f.hz()).sysclk(f.hz())...freeze(&mut flash.acr);
+            let settings = [
+                ("use_hse", clocks.hse),
+                ("sysclk", clocks.sysclk),
+                ("hclk", clocks.hclk),
+                ("pclk1", clocks.pclk1),
+                ("pclk2", clocks.pclk2),
+                ("adcclk", clocks.adcclk),
+            ];
+            let mut cfgr: syn::Expr = parse_quote!(#cfgr_ident);
+            for (function, frequency) in settings.iter() {
+                if let Some(frequency) = frequency {
+                    let function = format_ident!("{}", function);
+                    let frequency = frequency.0 as u32;
+                    cfgr = parse_quote!(#cfgr.#function(#frequency.hz()));
+                }
+            }
+            self.init_block.push(parse_quote!(
+                let #clocks_ident = #cfgr.freeze(&mut #flash_ident.acr);
             ));
             self.cfgr = None;
-            self.clocks = Some((clocks_ident, clock));
+            self.clocks = Some((clocks_ident, clocks));
         }
         self.clocks.as_ref().unwrap().0.clone()
     }
     fn gpios(&mut self, peripheral_config: &Stm32f1xxPeripherals) -> RefCell<HashSet<Ident>> {
         if self.gpios.is_none() {
-            let gpios = peripheral_config.used_gpios();
+            let gpios = used_gpios(&peripheral_config.components());
             let rcc_ident = self.rcc();
             let peripherals_ident = &self.peripherals;
             // First initialize the gpio ports
-            let ports: HashSet<&Port> = gpios.iter().map(|(_, port)| port).collect();
+            let ports: BTreeSet<&Port> = gpios.iter().map(|(_, port)| port).collect();
             for port in ports {
                 let port_lower = format_ident!("{}", port.lower());
                 let port_upper = format_ident!("{}", port.upper());
@@ -172,159 +199,348 @@ impl DeviceInit {
         }
         self.gpios.as_mut().unwrap().clone()
     }
-    fn inputs(&mut self, peripheral_config: &Stm32f1xxPeripherals) -> HashSet<InitializedGpio> {
-        let mut idents = HashSet::new();
+    /// Takes the gpio from the pool and brings it into a mode with the init function.
+    /// The binding is named after the gpio if no other name is given.
+    /// expand: let mut binding = gpiox.pxy.into_mode(&mut gpiox.control_reg);
+    fn init_gpio(
+        &mut self,
+        gpio_pool: &RefCell<HashSet<Ident>>,
+        (pin, port): (Pin, Port),
+        init_function_name: &str,
+        binding: Option<&str>,
+    ) -> Ident {
+        let port_ident = format_ident!("{}", port.lower());
+        let pin_name = gpio_pool
+            .borrow_mut()
+            .take(&gpio_ident(pin, port))
+            .expect("Use of uninitialized gpio");
+        let gpio_ident = match binding {
+            Some(binding) => format_ident!("{}", binding),
+            None => pin_name.clone(),
+        };
+        let control_reg = format_ident!("{}", control_reg(pin));
+        let init_function_name = format_ident!("{}", init_function_name);
+        self.init_block.push(parse_quote!(
+            let mut #gpio_ident = #port_ident.#pin_name.#init_function_name(&mut #port_ident.#control_reg);
+        ));
+        gpio_ident
+    }
+    fn inputs(&mut self, peripheral_config: &Stm32f1xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
         let inputs = &peripheral_config.gpio.input;
         let peripherals_ident = self.peripherals.clone();
-        let gpio_pool = self.gpios(&peripheral_config);
+        let gpio_pool = self.gpios(peripheral_config);
         for gpio in inputs {
-            let port_ident = format_ident!("{}", gpio.port.lower());
-            let gpio_ident = gpio_pool
-                .borrow_mut()
-                .take(&gpio_ident(gpio.pin, gpio.port))
-                .expect("Use of uninitialized gpio");
-            let pin_name = &gpio_ident; // Its only equal because we name the identifiers equally
-            let control_reg = format_ident!("{}", gpio.pin.control_reg());
-            let init_function_name = format_ident!("{}", gpio.mode.init_function_name());
-            self.init_block.push(parse_quote!(
-                let mut #gpio_ident = #port_ident.#pin_name.#init_function_name(&mut #port_ident.#control_reg);
-            ));
+            let gpio_ident = self.init_gpio(
+                &gpio_pool,
+                gpio.pin,
+                init_function_name(gpio.mode),
+                Some(&gpio.binding()),
+            );
             match gpio.interrupt_mode {
                 InterruptMode::None => {}
                 other => {
-                    let edge_ident = format_ident!("{}", other.ident());
+                    let edge_ident = format_ident!("{}", edge_name(other));
                     let afio_ident = self.afio();
-
-                    // expand:
-                    // pin_pxy.make_interrupt_source(&mut afio);
-                    // pin_pxy.trigger_on_edge(&peripherals.EXTI, Edge::EDGE_TYPE);
-                    // pin_pxy.enable_interrupt(&peripherals.EXTI);
-                    self.init_block.append(&mut parse_quote!(
-                        #gpio_ident.make_interrupt_source(&mut #afio_ident);
-                        #gpio_ident.trigger_on_edge(&#peripherals_ident.EXTI, stm32f1xx_hal::gpio::Edge::#edge_ident);
-                        #gpio_ident.enable_interrupt(&#peripherals_ident.EXTI);
+                    self.init_block.append(&mut interrupt_source(
+                        &gpio_ident,
+                        &afio_ident,
+                        &parse_quote!(&#peripherals_ident.EXTI),
+                        &parse_quote!(stm32f1xx_hal::gpio::Edge::#edge_ident),
                     ));
                 }
             }
-            idents.insert(InitializedGpio {
-                pin: gpio.pin,
-                port: gpio.port,
-                id: gpio_ident,
-                mode: gpio.mode,
+            let (pin, port) = gpio.pin;
+            idents.push(Initialized {
+                ty: gpio_type(pin, port, gpio.mode),
+                ident: gpio_ident,
             });
         }
         idents
     }
-    fn outputs(&mut self, peripheral_config: &Stm32f1xxPeripherals) -> HashSet<InitializedGpio> {
-        let mut idents = HashSet::new();
+    fn outputs(&mut self, peripheral_config: &Stm32f1xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
         let outputs = &peripheral_config.gpio.output;
         let gpio_pool = self.gpios(peripheral_config);
         for gpio in outputs {
-            let port_ident = format_ident!("{}", gpio.port.lower());
-            let gpio_ident = gpio_pool
-                .borrow_mut()
-                .take(&gpio_ident(gpio.pin, gpio.port))
-                .expect("Use of uninitialized gpio");
-            let pin_name = &gpio_ident; // Its only equal because we name the identifiers equally
-            let control_reg = format_ident!("{}", gpio.pin.control_reg());
-            let init_function_name = format_ident!("{}", gpio.mode.init_function_name());
+            let gpio_ident = self.init_gpio(
+                &gpio_pool,
+                gpio.pin,
+                init_function_name(gpio.mode),
+                Some(&gpio.binding()),
+            );
+            let (pin, port) = gpio.pin;
+            idents.push(Initialized {
+                ty: gpio_type(pin, port, gpio.mode),
+                ident: gpio_ident,
+            });
+        }
+        idents
+    }
+
+    fn timers(&mut self, peripheral_config: &Stm32f1xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        let clocks_ident = self
+            .clocks
+            .as_ref()
+            .expect("timers need frozen clocks")
+            .0
+            .clone();
+        let rcc_ident = self.rcc();
+        let peripherals_ident = &self.peripherals;
+        for timer in &peripheral_config.timer {
+            let timer_ident = format_ident!("{}", timer.id.lower());
+            let timer_upper = format_ident!("{}", timer.id.upper());
+            let apb = format_ident!("{}", timer.id.peripheral_bus());
+            // expand: let timx = Timer::timx(peripherals.TIMX, &clocks, &mut rcc.apbx);
             self.init_block.push(parse_quote!(
-                let mut #gpio_ident = #port_ident.#pin_name.#init_function_name(&mut #port_ident.#control_reg);
+                let #timer_ident = stm32f1xx_hal::timer::Timer::#timer_ident(#peripherals_ident.#timer_upper, &#clocks_ident, &mut #rcc_ident.#apb);
             ));
-            idents.insert(InitializedGpio {
-                pin: gpio.pin,
-                port: gpio.port,
-                id: gpio_ident,
-                mode: gpio.mode,
+            idents.push(Initialized {
+                ty: parse_str(&format!(
+                    "stm32f1xx_hal::timer::Timer<stm32f1xx_hal::pac::{}>",
+                    timer.id.upper()
+                ))
+                .unwrap(),
+                ident: timer_ident,
             });
         }
         idents
     }
 
-    fn static_init_and_return(
-        self,
-        inputs: &HashSet<InitializedGpio>,
-        outputs: &HashSet<InitializedGpio>,
-        timer: &HashSet<()>,
-        pwm: &HashSet<()>,
-        serial: &HashSet<()>,
-    ) -> (Vec<Stmt>, syn::Type) {
-        const IN_IDENT: &str = "INPUT_PINS";
-        const OUT_IDENT: &str = "OUTPUT_PINS";
-        const TIMER_IDENT: &str = "TIMERS";
-        const PWM_IDENT: &str = "PWM_PINS";
-        const SERIAL_IDENT: &str = "SERIALS";
-        fn static_init_stmt(static_name: &str, types: &Vec<syn::Type>) -> Stmt {
-            let static_name = format_ident!("{}", static_name);
-            parse_quote!(
-                static mut #static_name: MaybeUninit<(#(#types,)*)> = MaybeUninit::uninit();
-            )
-        }
-        fn write_static(static_name: &str, identifiers: &Vec<Ident>) -> syn::Expr {
-            let static_name = format_ident!("{}", static_name);
-            parse_quote!(
-                #static_name.write((#(#identifiers,)*))
-            )
+    fn pwms(&mut self, peripheral_config: &Stm32f1xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        let gpio_pool = self.gpios(peripheral_config);
+        let clocks_ident = self
+            .clocks
+            .as_ref()
+            .expect("pwm needs frozen clocks")
+            .0
+            .clone();
+        for pwm in &peripheral_config.pwm {
+            let timer = pwm.timer.id;
+            let (timer_remap, pin_channels) =
+                remap::timer_remap(timer, &pwm.pins).unwrap_or_else(|err| panic!("{}", err));
+            // the hal expects the pins ordered by channel
+            let mut channels: Vec<(Channel, Pin, Port)> = pin_channels
+                .into_iter()
+                .zip(pwm.pins.iter())
+                .map(|(channel, (pin, port))| (channel, *pin, *port))
+                .collect();
+            channels.sort();
+            let pin_idents: Vec<Ident> = channels
+                .iter()
+                .map(|(_, pin, port)| {
+                    self.init_gpio(
+                        &gpio_pool,
+                        (*pin, *port),
+                        ALTERNATE_PUSH_PULL,
+                        Some(&pwm.binding(*pin, *port)),
+                    )
+                })
+                .collect();
+            let rcc_ident = self.rcc();
+            let afio_ident = self.afio();
+            let peripherals_ident = &self.peripherals;
+            let timer_ident = format_ident!("{}", timer.lower());
+            let timer_upper = format_ident!("{}", timer.upper());
+            let timer_remap = format_ident!("{}", timer_remap);
+            let apb = format_ident!("{}", timer.peripheral_bus());
+            let frequency = pwm.frequency.unwrap_or(DEFAULT_PWM_FREQUENCY).0 as u32;
+            let (pins, channel_pat) = pwm_pins(&pin_idents);
+            // expand:
+            // let (pxy, ...) = Timer::timx(peripherals.TIMX, &clocks, &mut rcc.apbx)
+            //     .pwm::<TimxRemap, _, _, _>((pxy, ...), &mut afio.mapr, freq.hz())
+            //     .split();
+            self.init_block.push(parse_quote!(
+                let #channel_pat = stm32f1xx_hal::timer::Timer::#timer_ident(#peripherals_ident.#timer_upper, &#clocks_ident, &mut #rcc_ident.#apb)
+                    .pwm::<stm32f1xx_hal::timer::#timer_remap, _, _, _>(#pins, &mut #afio_ident.mapr, #frequency.hz())
+                    .split();
+            ));
+            self.init_block
+                .append(&mut initial_duty(&pin_idents, pwm.duty));
+            for ((channel, _, _), ident) in channels.into_iter().zip(pin_idents) {
+                idents.push(Initialized {
+                    ty: parse_str(&format!(
+                        "stm32f1xx_hal::pwm::PwmChannel<stm32f1xx_hal::pac::{}, stm32f1xx_hal::pwm::{}>",
+                        timer.upper(),
+                        channel.name()
+                    ))
+                    .unwrap(),
+                    ident,
+                });
+            }
         }
-        let in_tys = inputs.iter().map(|gpio| gpio.ty()).collect();
-        let out_tys = outputs.iter().map(|gpio| gpio.ty()).collect();
-        let timer_tys = timer.iter().map(|timer| timer.ty()).collect();
-        let pwm_tys = pwm.iter().map(|pwm| pwm.ty()).collect();
-        let serial_tys = serial.iter().map(|serial| serial.ty()).collect();
-
-        let in_ids = inputs.iter().map(|gpio| gpio.identifier()).collect();
-        let out_ids = outputs.iter().map(|gpio| gpio.identifier()).collect();
-        let timer_ids = timer.iter().map(|timer| timer.identifier()).collect();
-        let pwm_ids = pwm.iter().map(|pwm| pwm.identifier()).collect();
-        let serial_ids = serial.iter().map(|serial| serial.identifier()).collect();
-
-        let mut stmts = self.init_block;
-
-        stmts.push(static_init_stmt(IN_IDENT, &in_tys));
-        stmts.push(static_init_stmt(OUT_IDENT, &out_tys));
-        stmts.push(static_init_stmt(TIMER_IDENT, &timer_tys));
-        stmts.push(static_init_stmt(PWM_IDENT, &pwm_tys));
-        stmts.push(static_init_stmt(SERIAL_IDENT, &serial_tys));
-
-        let init_ins = write_static(IN_IDENT, &in_ids);
-        let init_outs = write_static(OUT_IDENT, &out_ids);
-        let init_timer = write_static(TIMER_IDENT, &timer_ids);
-        let init_pwm = write_static(PWM_IDENT, &pwm_ids);
-        let init_serial = write_static(SERIAL_IDENT, &serial_ids);
+        idents
+    }
 
-        stmts.push(parse_quote!(
-            unsafe{(
-                (#init_ins),
-                (#init_outs),
-                (#init_timer),
-                (#init_pwm),
-                (#init_serial),
-            )}
-        ));
-        let return_types = parse_quote!(
-            (
-                &'static mut (#(#in_tys,)*),
-                &'static mut (#(#out_tys,)*),
-                &'static mut (#(#timer_tys,)*),
-                &'static mut (#(#pwm_tys,)*),
-                &'static mut (#(#serial_tys,)*),
-            )
-        );
-        (stmts, return_types)
+    fn serials(&mut self, peripheral_config: &Stm32f1xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        let gpio_pool = self.gpios(peripheral_config);
+        let clocks_ident = self
+            .clocks
+            .as_ref()
+            .expect("serials need frozen clocks")
+            .0
+            .clone();
+        for serial in &peripheral_config.serial {
+            // the hal selects the remap from the pin types
+            if let Err(err) = remap::serial_remap(serial.id, serial.tx, serial.rx) {
+                panic!("{}", err);
+            }
+            let tx_pin = self.init_gpio(&gpio_pool, serial.tx, ALTERNATE_PUSH_PULL, None);
+            let rx_pin = self.init_gpio(
+                &gpio_pool,
+                serial.rx,
+                init_function_name(PinMode::InputFloating),
+                None,
+            );
+            let rcc_ident = self.rcc();
+            let afio_ident = self.afio();
+            let peripherals_ident = &self.peripherals;
+            let serial_ident = format_ident!("{}", serial.binding());
+            let serial_constructor = format_ident!("{}", serial.id.lower());
+            let serial_upper = format_ident!("{}", serial.id.upper());
+            let apb = format_ident!("{}", serial.id.peripheral_bus());
+            let baud = serial.baud_rate.0;
+            let parity = format_ident!("{}", serial.parity.config_function_name());
+            let stop_bits = format_ident!("{}", serial.stop_bits.ident());
+            let word_length = format_ident!(
+                "{}",
+                word_length_function_name(
+                    serial
+                        .frame_length()
+                        .unwrap_or_else(|err| panic!("{}", err))
+                )
+            );
+            let tx_ident = format_ident!("{}", serial.tx_binding());
+            let rx_ident = format_ident!("{}", serial.rx_binding());
+            // expand:
+            // let usartx = Serial::usartx(peripherals.USARTX, (tx, rx), &mut afio.mapr, config, clocks, &mut rcc.apbx);
+            // let (usartx_tx, usartx_rx) = usartx.split();
+            self.init_block.append(&mut parse_quote!(
+                let #serial_ident = stm32f1xx_hal::serial::Serial::#serial_constructor(
+                    #peripherals_ident.#serial_upper,
+                    (#tx_pin, #rx_pin),
+                    &mut #afio_ident.mapr,
+                    stm32f1xx_hal::serial::Config::default()
+                        .baudrate(#baud.bps())
+                        .#parity()
+                        .stopbits(stm32f1xx_hal::serial::StopBits::#stop_bits)
+                        .#word_length(),
+                    #clocks_ident,
+                    &mut #rcc_ident.#apb
+                );
+                let (mut #tx_ident, mut #rx_ident) = #serial_ident.split();
+            ));
+            if serial.interrupt.rx() {
+                self.init_block.push(parse_quote!(#rx_ident.listen();));
+            }
+            if serial.interrupt.tx() {
+                self.init_block.push(parse_quote!(#tx_ident.listen();));
+            }
+            for (half, ident) in [("Tx", tx_ident), ("Rx", rx_ident)] {
+                idents.push(Initialized {
+                    ty: parse_str(&format!(
+                        "stm32f1xx_hal::serial::{}<stm32f1xx_hal::pac::{}>",
+                        half,
+                        serial.id.upper()
+                    ))
+                    .unwrap(),
+                    ident,
+                });
+            }
+        }
+        idents
     }
 
-    pub(crate) fn get_init_block(config: &DeviceConfig) -> (Vec<Stmt>, syn::Type) {
-        let peripheral_config = match &config.kind {
-            crate::device::DeviceKind::Stm32f1xx(pc) => pc,
-            _ => panic!("Tried to build stm32f1xx config from other device kind"),
-        };
+    pub(crate) fn get_init_block(
+        peripheral_config: &Stm32f1xxPeripherals,
+        item_struct: &ItemStruct,
+    ) -> (Vec<Stmt>, syn::Type, Vec<Item>) {
         let mut device_init = DeviceInit::new();
-        device_init.clocks(config.clock); // TODO: may change to take a config
-        let inputs = device_init.inputs(&peripheral_config);
-        let outputs = device_init.outputs(&peripheral_config);
-        let timer = HashSet::new();
-        let pwm = HashSet::new();
-        let serial = HashSet::new();
-        device_init.static_init_and_return(&inputs, &outputs, &timer, &pwm, &serial)
+        device_init.clocks(peripheral_config.clocks.requested);
+        let inputs = device_init.inputs(peripheral_config);
+        let outputs = device_init.outputs(peripheral_config);
+        let timer = device_init.timers(peripheral_config);
+        let pwm = device_init.pwms(peripheral_config);
+        let serial = device_init.serials(peripheral_config);
+        static_init_and_return(
+            device_init.init_block,
+            item_struct,
+            ComponentGroups {
+                inputs: fields(&inputs),
+                outputs: fields(&outputs),
+                timers: fields(&timer),
+                pwm: fields(&pwm),
+                serial: fields(&serial),
+            },
+        )
     }
 }
+
+/// Reports the frequencies the hal configures for the requested clocks,
+/// which may be rounded down from the values in the yaml.
+pub(crate) fn clock_consts(peripheral_config: &Stm32f1xxPeripherals) -> Vec<syn::ImplItem> {
+    let clocks = &peripheral_config.clocks;
+    let mut consts = frequency_consts(&[
+        ("SYSCLK", clocks.sysclk),
+        ("HCLK", clocks.hclk),
+        ("PCLK1", clocks.pclk1),
+        ("PCLK2", clocks.pclk2),
+        ("ADCCLK", clocks.adcclk),
+    ]);
+    let flash_latency = clocks.flash_latency;
+    consts.push(parse_quote!(const FLASH_LATENCY: u8 = #flash_latency;));
+    consts
+}
+
+/// Unmasks the interrupts of all interrupting inputs and serials in the NVIC.
+/// Inputs can share an interrupt (e.g. EXTI9_5), which is unmasked only once.
+/// Configured priorities are set before any interrupt is unmasked.
+pub(crate) fn interrupt_unmasks(peripheral_config: &Stm32f1xxPeripherals) -> Vec<Stmt> {
+    let interrupts = peripheral_config
+        .interrupts()
+        .unwrap_or_else(|errors| panic!("{}", errors[0]));
+    nvic_unmasks(
+        &parse_quote!(stm32f1xx_hal::pac),
+        &interrupts,
+        NVIC_PRIO_BITS,
+    )
+}
+
+#[test]
+fn interrupt_unmasks_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: stm32f1xx
+clock: 8mhz
+gpio:
+  input:
+    - pa5:
+      mode: floating
+      interrupt: rising
+    - pb9:
+      mode: floating
+      interrupt: falling
+    - pc13:
+      mode: floating
+      interrupt: rising_falling
+      priority: 2
+",
+    )
+    .unwrap();
+    let unmasks = crate::device::DeviceConfig::from_yaml(&yaml[0])
+        .unwrap()
+        .get_interrupt_unmasks();
+    assert_eq!(
+        quote::quote!(#(#unmasks)*).to_string(),
+        quote::quote!(
+            let mut nvic = stm32f1xx_hal::pac::CorePeripherals::steal().NVIC;
+            nvic.set_priority(stm32f1xx_hal::pac::Interrupt::EXTI15_10, 32u8);
+            stm32f1xx_hal::pac::NVIC::unmask(stm32f1xx_hal::pac::Interrupt::EXTI15_10);
+            stm32f1xx_hal::pac::NVIC::unmask(stm32f1xx_hal::pac::Interrupt::EXTI9_5);
+        )
+        .to_string()
+    );
+}
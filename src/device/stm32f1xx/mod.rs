@@ -1,12 +1,42 @@
-use core::panic;
-use syn::parse_quote;
+use std::collections::BTreeMap;
 use yaml_rust::Yaml;
 
+mod clocks;
 mod generation;
+mod remap;
 
-use self::generation::DeviceInit;
+use self::{clocks::ClockTree, generation::DeviceInit};
 
-use super::{Baud, DeviceConfig, Hertz};
+use super::{
+    check_components, clocks_yaml, parse_list,
+    stm32::{self, gpio_name, Channel, GpioPort, Peripheral, Pin, NVIC_PRIO_BITS},
+    Clocks, Component, Device, Hertz, PeripheralId, Priority, Tolerance,
+};
+use crate::error::{describe, Error};
+
+#[cfg(test)]
+use super::messages;
+
+type Gpios = super::Gpios<(Pin, Port)>;
+type Serial = super::Serial<SerialID, (Pin, Port)>;
+type Timer = super::Timer<TimerID>;
+#[allow(clippy::upper_case_acronyms)]
+type PWM = stm32::PWM<TimerID, Port>;
+
+/// Bindings of the generated init function that are not components
+const RESERVED_BINDINGS: &[&str] = &[
+    "peripherals",
+    "flash",
+    "rcc",
+    "cfgr",
+    "afio",
+    "clocks",
+    "gpioa",
+    "gpiob",
+    "gpioc",
+    "gpiod",
+    "gpioe",
+];
 
 #[derive(Debug)]
 pub struct Stm32f1xxPeripherals {
@@ -14,328 +44,226 @@ pub struct Stm32f1xxPeripherals {
     timer: Vec<Timer>,
     pwm: Vec<PWM>,
     serial: Vec<Serial>,
+    clocks: ClockTree,
 }
 
-impl Stm32f1xxPeripherals {
-    pub fn from_yaml(yaml: &Yaml) -> Self {
+impl Device for Stm32f1xxPeripherals {
+    fn kinds() -> &'static [&'static str] {
+        &["stm32f1xx", "bluepill"]
+    }
+    /// Parses the peripherals and checks them against the hardware constraints.
+    /// The constraints are only checked if all components could be parsed.
+    fn from_yaml(yaml: &Yaml, clocks: Clocks) -> Result<Self, Vec<Error>> {
+        let mut errors = Vec::new();
         let peripherals = Self {
-            gpio: Gpios {
-                input: yaml["gpio"]["input"]
-                    .as_vec()
-                    .map(|ins| {
-                        ins.iter()
-                            .map(|yaml| Gpio::input_from_yaml(&yaml))
-                            .collect()
-                    })
-                    .unwrap_or_default(),
-                output: yaml["gpio"]["output"]
-                    .as_vec()
-                    .map(|outs| {
-                        outs.iter()
-                            .map(|yaml| Gpio::output_from_yaml(&yaml))
-                            .collect()
-                    })
-                    .unwrap_or_default(),
-            },
-            timer: yaml["timer"]
-                .as_vec()
-                .map(|timers| timers.iter().map(|timer| Timer::from_yaml(timer)).collect())
-                .unwrap_or_default(),
-            pwm: yaml["pwm"]
-                .as_vec()
-                .map(|pwms| pwms.iter().map(|pwm| PWM::from_yaml(pwm)).collect())
-                .unwrap_or_default(),
-            serial: yaml["serial"]
-                .as_vec()
-                .map(|serials| {
-                    serials
-                        .iter()
-                        .map(|serial| Serial::from_yaml(serial))
-                        .collect()
-                })
-                .unwrap_or_default(),
+            gpio: Gpios::from_yaml(&yaml["gpio"], NVIC_PRIO_BITS, &mut errors),
+            timer: parse_list(&yaml["timer"], Timer::from_yaml, &mut errors),
+            pwm: parse_list(&yaml["pwm"], PWM::from_yaml, &mut errors),
+            serial: parse_list(
+                &yaml["serial"],
+                |serial| Serial::from_yaml(serial, NVIC_PRIO_BITS),
+                &mut errors,
+            ),
+            clocks: ClockTree::solve(clocks),
         };
-        peripherals.check();
-        peripherals
+        if errors.is_empty() {
+            errors = peripherals.check();
+        }
+        for error in peripherals.clocks.check() {
+            errors.push(Error::at(clocks_yaml(yaml), error));
+        }
+        if errors.is_empty() {
+            Ok(peripherals)
+        } else {
+            Err(errors)
+        }
     }
-    fn check(&self) {
-        // TODO: check that timers are captured only once
-        // TODO: check gpio and peripheral combination is possible
+    fn init_fn(
+        &self,
+        item_struct: &syn::ItemStruct,
+    ) -> (Vec<syn::Stmt>, syn::Type, Vec<syn::Item>) {
+        DeviceInit::get_init_block(self, item_struct)
     }
-    fn used_gpios(&self) -> Vec<(Pin, Port)> {
-        let mut inputs: Vec<(Pin, Port)> = self
-            .gpio
-            .input
-            .iter()
-            .map(|gpio| (gpio.pin, gpio.port))
-            .collect::<Vec<(Pin, Port)>>();
-        let outputs = self
-            .gpio
-            .output
-            .iter()
-            .map(|gpio| (gpio.pin, gpio.port))
-            .collect::<Vec<(Pin, Port)>>();
-        let pwm = self
-            .pwm
-            .iter()
-            .map(|pwm| pwm.pins.clone())
-            .flatten()
-            .collect::<Vec<(Pin, Port)>>();
-        let serial = self
-            .serial
-            .iter()
-            .map(|serial| vec![serial.tx, serial.rx])
-            .flatten()
-            .collect::<Vec<(Pin, Port)>>();
-        inputs.extend_from_slice(&outputs);
-        inputs.extend_from_slice(&pwm);
-        inputs.extend_from_slice(&serial);
-        inputs
+    fn clock_consts(&self) -> Vec<syn::ImplItem> {
+        generation::clock_consts(self)
+    }
+    fn interrupt_unmasks(&self) -> Vec<syn::Stmt> {
+        generation::interrupt_unmasks(self)
     }
 }
 
-#[derive(Debug)]
-pub struct Serial {
-    id: SerialID,
-    rx: (Pin, Port),
-    tx: (Pin, Port),
-    baud_rate: Baud,
+impl Stm32f1xxPeripherals {
+    /// Checks the timers and serials against their remaps and bus clocks
+    /// and the interrupting inputs against the shared EXTI lines.
+    fn check(&self) -> Vec<Error> {
+        let mut errors = stm32::check_peripherals(&self.timer, &self.pwm, &self.serial, |bus| {
+            self.clocks.timer_clock(bus)
+        });
+        for serial in &self.serial {
+            if let Err(err) = remap::serial_remap(serial.id, serial.tx, serial.rx) {
+                errors.push(Error::new(serial.location, err));
+            }
+            let pclk = self.clocks.bus_clock(serial.id.peripheral_bus());
+            if let Err(err) = serial.baud_rate_divisor(pclk) {
+                errors.push(Error::new(serial.location, err));
+            }
+        }
+        for pwm in &self.pwm {
+            match remap::timer_remap(pwm.timer.id, &pwm.pins) {
+                Ok((_, channels)) => errors.append(&mut pwm.check_channels(channels)),
+                Err(err) => errors.push(Error::new(pwm.location, err)),
+            }
+        }
+        errors.append(&mut check_components(RESERVED_BINDINGS, &self.components()));
+        if let Err(mut interrupt_errors) = self.interrupts() {
+            errors.append(&mut interrupt_errors);
+        }
+        errors
+    }
+    /// All nvic interrupts of the configured sources and their priority
+    fn interrupts(&self) -> Result<BTreeMap<String, Option<Priority>>, Vec<Error>> {
+        stm32::interrupts(&self.gpio.input, &self.serial)
+    }
+    /// The components in the order of the yaml sections
+    fn components(&self) -> [&dyn Component<(Pin, Port)>; 4] {
+        [&self.gpio, &self.timer, &self.pwm, &self.serial]
+    }
 }
-#[derive(Debug)]
+
+impl Serial {
+    /// The hal divides the bus clock by the baud rate to get the value of the
+    /// baud rate register (BRR), which must fit into 16 bits and allow
+    /// 16 times oversampling. The truncated division changes the actual
+    /// baud rate, which has to be within the tolerance of the requested one.
+    fn baud_rate_divisor(&self, pclk: Hertz) -> Result<u32, String> {
+        let baud = self.baud_rate.0 as u64;
+        let brr = pclk.0 as u64 / baud;
+        if !(16..=0xFFFF).contains(&brr) {
+            return Err(format!(
+                "Baud rate {} of serial '{}' cannot be generated from its bus clock of {}",
+                baud,
+                self.id.lower(),
+                pclk
+            ));
+        }
+        let actual = pclk.0 as u64 / brr;
+        let deviation = Tolerance::deviation(baud, actual);
+        if deviation > self.baud_tolerance {
+            return Err(format!(
+                "Baud rate {} of serial '{}' is generated as {} from its bus clock of {}, a deviation of {} exceeds the tolerance of {}",
+                baud,
+                self.id.lower(),
+                actual,
+                pclk,
+                deviation,
+                self.baud_tolerance
+            ));
+        }
+        Ok(brr as u32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum SerialID {
     Usart1,
     Usart2,
     Usart3,
 }
 
-impl Serial {
-    fn from_yaml(yaml: &Yaml) -> Self {
-        let config = yaml.as_hash().expect("Unexpected input serial format");
-        let mut serial_name = None;
-        for entry in config {
-            match entry {
-                (Yaml::String(k), Yaml::Null) => match serial_name {
-                    Some(_) => unreachable!(),
-                    None => {
-                        serial_name = Some(k);
-                        break;
-                    }
-                },
-                _ => {}
-            }
-        }
-        Self {
-            id: SerialID::from_str(&serial_name.expect("Unknown serial ID")),
-            rx: Gpio::parse_pin(&Some(
-                yaml["rx"].as_str().expect("Missing 'rx' gpio in serial"),
-            )),
-            tx: Gpio::parse_pin(&Some(
-                yaml["tx"].as_str().expect("Missing 'tx' gpio in serial"),
-            )),
-            baud_rate: Baud::from_i64(
-                yaml["baud"]
-                    .as_i64()
-                    .expect("Missing 'baud' rate in serial"),
-            ),
+impl PeripheralId for SerialID {
+    const EXAMPLE: &'static str = "usart1";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        yaml.as_str()
+            .ok_or_else(|| format!("Unknown serial {}", describe(yaml)))
+            .and_then(Self::from_str)
+            .map_err(|e| Error::at(yaml, e))
+    }
+    fn lower(&self) -> &str {
+        match self {
+            SerialID::Usart1 => "usart1",
+            SerialID::Usart2 => "usart2",
+            SerialID::Usart3 => "usart3",
         }
     }
 }
 
 impl SerialID {
-    fn from_str(str: &str) -> Self {
+    fn from_str(str: &str) -> Result<Self, String> {
         match str.to_lowercase().as_str() {
-            "usart1" => Self::Usart1,
-            "usart2" => Self::Usart2,
-            "usart3" => Self::Usart3,
-            other => panic!("Unknown serial name '{:?}'", other),
+            "usart1" => Ok(Self::Usart1),
+            "usart2" => Ok(Self::Usart2),
+            "usart3" => Ok(Self::Usart3),
+            _ => Err(format!(
+                "Unknown serial '{}' (usart1, usart2 or usart3)",
+                str
+            )),
         }
     }
 }
 
-#[derive(Debug)]
-pub struct PWM {
-    timer: Timer,
-    pins: Vec<(Pin, Port)>,
-    frequency: Option<Hertz>,
-}
-
-impl PWM {
-    fn from_yaml(yaml: &Yaml) -> Self {
-        let config = yaml.as_hash().expect("Unexpected input pwm format");
-        let mut timer_name = None;
-        for entry in config {
-            match entry {
-                (k, Yaml::Null) => match timer_name {
-                    Some(_) => unreachable!(),
-                    None => {
-                        timer_name = Some(k);
-                        break;
-                    }
-                },
-                _ => {}
-            }
+impl Peripheral for SerialID {
+    fn upper(&self) -> &str {
+        match self {
+            SerialID::Usart1 => "USART1",
+            SerialID::Usart2 => "USART2",
+            SerialID::Usart3 => "USART3",
         }
-        Self {
-            timer: Timer::from_yaml(timer_name.expect("no timer found for pwm")),
-            pins: yaml["pins"]
-                .clone()
-                .into_iter()
-                .map(|pin| Gpio::parse_pin(&pin.as_str()))
-                .collect(),
-            frequency: yaml["freq"].as_str().map(|f| Hertz::from_str(f)),
+    }
+    fn peripheral_bus(&self) -> &str {
+        match self {
+            SerialID::Usart1 => "apb2",
+            SerialID::Usart2 | SerialID::Usart3 => "apb1",
         }
     }
 }
 
-#[derive(Debug)]
-struct Timer {
-    id: TimerID,
-}
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum TimerID {
     Tim1,
     Tim2,
     Tim3,
+    Tim4,
 }
 
-impl Timer {
-    fn from_yaml(yaml: &Yaml) -> Self {
-        let id = match yaml
-            .as_str()
-            .expect("Unable to parse timer")
-            .to_lowercase()
-            .as_str()
-        {
-            "tim1" => TimerID::Tim1,
-            "tim2" => TimerID::Tim2,
-            "tim3" => TimerID::Tim3,
-            other => panic!("Unknown timer '{}'", other),
-        };
-        Self { id }
-    }
-}
-
-#[derive(Debug)]
-struct Gpios {
-    input: Vec<Gpio>,
-    output: Vec<Gpio>,
-}
-#[derive(Clone, Copy, Debug)]
-pub struct Gpio {
-    pin: Pin,
-    port: Port,
-    mode: PinMode,
-    interrupt_mode: InterruptMode,
-}
-
-impl Gpio {
-    pub fn input_from_yaml(gpio_yaml: &Yaml) -> Self {
-        let config = gpio_yaml.as_hash().expect("Unexpected input gpio format");
-        let mut pin_name = None;
-        for entry in config {
-            match entry {
-                (Yaml::String(k), Yaml::Null) => match pin_name {
-                    Some(_) => unreachable!(),
-                    None => pin_name = Some(k.as_str()),
-                },
-                _ => {}
-            }
-        }
-        let (pin, port) = Self::parse_pin(&pin_name);
-        let mode = match gpio_yaml["mode"]
-            .as_str()
-            .expect("Missing key 'mode' in input pin")
-        {
-            "pull_up" => PinMode::InputPullUp,
-            "pull_down" => PinMode::InputPullDown,
-            "floating" => PinMode::InputFloating,
-            other => panic!("Unable to parse mode {:?}", other),
-        };
-        let interrupt_mode = match gpio_yaml["interrupt"].as_str() {
-            Some("rising") => InterruptMode::Rising,
-            Some("falling") => InterruptMode::Falling,
-            Some("rising_falling") => InterruptMode::RisingFalling,
-            Some("none") => InterruptMode::None,
-            None => InterruptMode::None,
-            Some(other) => panic!("Unable to parse interrupt mode '{:?}'", other),
-        };
-        Gpio {
-            pin,
-            port,
-            mode,
-            interrupt_mode,
+impl PeripheralId for TimerID {
+    const EXAMPLE: &'static str = "tim2";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml.as_str().map(str::to_lowercase).as_deref() {
+            Some("tim1") => Ok(TimerID::Tim1),
+            Some("tim2") => Ok(TimerID::Tim2),
+            Some("tim3") => Ok(TimerID::Tim3),
+            Some("tim4") => Ok(TimerID::Tim4),
+            _ => Err(Error::at(
+                yaml,
+                format!("Unknown timer {} (tim1 to tim4)", describe(yaml)),
+            )),
         }
     }
-    pub fn output_from_yaml(gpio_yaml: &Yaml) -> Self {
-        let config = match gpio_yaml {
-            Yaml::Hash(hash) => hash,
-            _ => panic!("Unexpected input gpio format"),
-        };
-        let mut pin_name = None;
-        let mut pin_mode = None;
-        for entry in config {
-            match entry {
-                (Yaml::String(k), Yaml::String(v)) => match pin_name {
-                    Some(_) => panic!(
-                        "Expected a single mode element for output gpio key (e.g. pb5: push_pull"
-                    ),
-                    None => {
-                        pin_name = Some(k.as_str());
-                        pin_mode = Some(v)
-                    }
-                },
-                (k, v) => {
-                    panic!("unknown input gpio config {:?}: {:?}", k, v)
-                }
-            }
-        }
-        let (pin, port) = Self::parse_pin(&pin_name);
-        let mode = match pin_mode
-            .expect("Unable to parse output pin mode")
-            .to_lowercase()
-            .as_str()
-        {
-            "push_pull" => PinMode::OutputPushPull,
-            "open_drain" => PinMode::OutputOpenDrain,
-            _ => panic!("Unable to parse output pin mode"),
-        };
-        Gpio {
-            pin,
-            port,
-            mode,
-            interrupt_mode: InterruptMode::None,
+    fn lower(&self) -> &str {
+        match self {
+            TimerID::Tim1 => "tim1",
+            TimerID::Tim2 => "tim2",
+            TimerID::Tim3 => "tim3",
+            TimerID::Tim4 => "tim4",
         }
     }
-    fn parse_pin(key: &Option<&str>) -> (Pin, Port) {
-        let string = key.expect("could not parse pin name").to_lowercase();
-        let string = match string.strip_prefix("p") {
-            Some(s) => s,
-            None => &string,
-        };
-        let (port, pin) = string.split_at(1);
-        (
-            Pin(pin.parse::<usize>().expect("Unable to parse pin number")),
-            match port {
-                "a" => Port::A,
-                "b" => Port::B,
-                "c" => Port::C,
-                "d" => Port::D,
-                "e" => Port::E,
-                _ => panic!("unable to parse port"),
-            },
-        )
-    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Pin(usize);
-
-impl Pin {
-    fn control_reg(&self) -> &str {
-        if (self.0 % 16) < 8 {
-            "crl"
-        } else {
-            "crh"
+impl Peripheral for TimerID {
+    fn upper(&self) -> &str {
+        match self {
+            TimerID::Tim1 => "TIM1",
+            TimerID::Tim2 => "TIM2",
+            TimerID::Tim3 => "TIM3",
+            TimerID::Tim4 => "TIM4",
+        }
+    }
+    /// The advanced timer is clocked by the high speed bus,
+    /// the general purpose timers by the low speed bus.
+    fn peripheral_bus(&self) -> &str {
+        match self {
+            TimerID::Tim1 => "apb2",
+            TimerID::Tim2 | TimerID::Tim3 | TimerID::Tim4 => "apb1",
         }
     }
 }
@@ -349,23 +277,16 @@ pub enum Port {
     E,
 }
 
-impl Port {
-    fn lower(&self) -> &str {
-        match self {
-            Port::A => "gpioa",
-            Port::B => "gpiob",
-            Port::C => "gpioc",
-            Port::D => "gpiod",
-            Port::E => "gpioe",
-        }
-    }
-    fn upper(&self) -> &str {
-        match self {
-            Port::A => "GPIOA",
-            Port::B => "GPIOB",
-            Port::C => "GPIOC",
-            Port::D => "GPIOD",
-            Port::E => "GPIOE",
+impl GpioPort for Port {
+    const LETTERS: &'static str = "a to e";
+    fn from_short(short: char) -> Option<Self> {
+        match short {
+            'a' => Some(Port::A),
+            'b' => Some(Port::B),
+            'c' => Some(Port::C),
+            'd' => Some(Port::D),
+            'e' => Some(Port::E),
+            _ => None,
         }
     }
     fn short(&self) -> char {
@@ -379,66 +300,183 @@ impl Port {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum PinMode {
-    InputFloating,
-    InputPullUp,
-    InputPullDown,
-    OutputPushPull,
-    OutputOpenDrain,
+#[test]
+fn check_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+gpio:
+  input:
+    - pa0:
+      mode: floating
+      interrupt: rising
+    - pb0:
+      mode: floating
+      interrupt: falling
+    - pa5:
+      mode: floating
+      interrupt: falling
+      priority: 1
+    - pb6:
+      mode: floating
+      interrupt: falling
+      priority: 2
+    - pc7:
+      mode: floating
+      interrupt: falling
+    - pa16:
+      mode: floating
+      interrupt: rising
+timer:
+  - tim2
+pwm:
+  - tim2:
+    pins:
+      - pa6
+serial:
+  - usart1:
+    tx: pa9
+    rx: pa10
+    baud: 9600
+  - usart1:
+    tx: pb6
+    rx: pb7
+    baud: 9600
+  - usart2:
+    tx: pa2
+    rx: pa3
+    baud: 9600
+    word_length: 6
+",
+    )
+    .unwrap();
+    let clocks = Clocks {
+        sysclk: Some(Hertz(100_000_000)),
+        ..Clocks::default()
+    };
+    let message = messages(Stm32f1xxPeripherals::from_yaml(&yaml[0], clocks).unwrap_err());
+    assert!(message.contains("Clock 'sysclk' of 100mhz exceeds the maximum of 72mhz"));
+    assert!(message.contains("Timer 'tim2' is used in 'timer' and in 'pwm'"));
+    assert!(message.contains("Serial 'usart1' is used multiple times"));
+    assert!(message.contains(
+        "Serial 'usart2' supports 8 or 9 bit frames, but 6 data bits and parity 'None' need 6 bits"
+    ));
+    assert!(message.contains("Gpios (pa6) cannot be mapped on the channels of timer 'tim2'"));
+    assert!(message
+        .contains("Gpio 'pa16' does not exist, the pins of port a are numbered from 0 to 15"));
+    assert!(message.contains("Gpios 'pa0' and 'pb0' both use interrupt line EXTI0"));
+    assert!(message
+        .contains("'pa5' and 'pb6' share the interrupt EXTI9_5 but have different priorities"));
+    assert!(message.contains(
+        "'pa5' and 'pc7' share the interrupt EXTI9_5 but have different priorities (1 and unset)"
+    ));
 }
 
-impl PinMode {
-    fn init_function_name(&self) -> &str {
-        match self {
-            PinMode::InputFloating => "into_floating_input",
-            PinMode::InputPullUp => "into_pull_up_input",
-            PinMode::InputPullDown => "into_pull_down_input",
-            PinMode::OutputPushPull => "into_open_drain_output",
-            PinMode::OutputOpenDrain => "into_push_pull_output",
-        }
-    }
-    fn direction_name(&self) -> &str {
-        match self {
-            PinMode::InputFloating => "Input",
-            PinMode::InputPullUp => "Input",
-            PinMode::InputPullDown => "Input",
-            PinMode::OutputPushPull => "Output",
-            PinMode::OutputOpenDrain => "Output",
-        }
-    }
-    fn mode_name(&self) -> &str {
-        match self {
-            PinMode::InputFloating => "Floating",
-            PinMode::InputPullUp => "PullUp",
-            PinMode::InputPullDown => "PullDown",
-            PinMode::OutputPushPull => "OpenDrain",
-            PinMode::OutputOpenDrain => "PushPull",
-        }
-    }
+#[test]
+fn exti_line_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+gpio:
+  input:
+    - pa3:
+      mode: floating
+      interrupt: rising
+    - pc3:
+      mode: pull_up
+      interrupt: falling
+    - pd3:
+      mode: pull_up
+    - pb4:
+      mode: pull_up
+      interrupt: falling
+",
+    )
+    .unwrap();
+    let inputs: Vec<super::Gpio<(Pin, Port)>> = yaml[0]["gpio"]["input"]
+        .as_vec()
+        .unwrap()
+        .iter()
+        .map(|gpio| super::Gpio::input_from_yaml(gpio, NVIC_PRIO_BITS).unwrap())
+        .collect();
+    assert_eq!(
+        messages(stm32::exti_lines(&inputs).unwrap_err()),
+        "Gpios 'pa3' and 'pc3' both use interrupt line EXTI3"
+    );
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum InterruptMode {
-    None,
-    Rising,
-    Falling,
-    RisingFalling,
-}
-
-impl InterruptMode {
-    fn ident(&self) -> &str {
-        match self {
-            InterruptMode::None => {
-                panic!("InterruptMode::None cannot be converted into an identifier")
-            }
-            InterruptMode::Rising => "RISING",
-            InterruptMode::Falling => "FALLING",
-            InterruptMode::RisingFalling => "RISING_FALLING",
-        }
-    }
+#[test]
+fn baud_rate_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+- usart2:
+  tx: pa2
+  rx: pa3
+  baud: 115200
+- usart2:
+  tx: pa2
+  rx: pa3
+  baud: 460800
+- usart2:
+  tx: pa2
+  rx: pa3
+  baud: 460800
+  baud_tolerance: 3%
+",
+    )
+    .unwrap();
+    let serials: Vec<Serial> = yaml[0]
+        .as_vec()
+        .unwrap()
+        .iter()
+        .map(|serial| Serial::from_yaml(serial, NVIC_PRIO_BITS).unwrap())
+        .collect();
+    assert_eq!(serials[0].baud_rate_divisor(Hertz(8_000_000)), Ok(69));
+    assert!(serials[0].baud_rate_divisor(Hertz(1_000_000)).is_err());
+    assert_eq!(
+        serials[1].baud_rate_divisor(Hertz(8_000_000)),
+        Err("Baud rate 460800 of serial 'usart2' is generated as 470588 from its bus clock of 8mhz, a deviation of 2.12% exceeds the tolerance of 2%".to_string())
+    );
+    assert_eq!(serials[2].baud_rate_divisor(Hertz(8_000_000)), Ok(17));
 }
 
-pub(super) fn init_stmts_and_return_tys(config: &DeviceConfig) -> (Vec<syn::Stmt>, syn::Type) {
-    DeviceInit::get_init_block(config)
+#[test]
+fn names_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+gpio:
+  input:
+    - pa0:
+      mode: floating
+      name: status led
+    - pa1:
+      mode: floating
+      name: type
+  output:
+    - pc13: push_pull
+      name: led
+    - pc14: push_pull
+      name: led
+    - pc15: push_pull
+      name: clocks
+pwm:
+  - tim2:
+    pins:
+      - pa2:
+        name: pb6
+serial:
+  - usart1:
+    tx: pb6
+    rx: pb7
+    baud: 9600
+    name: debug
+",
+    )
+    .unwrap();
+    let message =
+        messages(Stm32f1xxPeripherals::from_yaml(&yaml[0], Clocks::default()).unwrap_err());
+    assert!(message.contains("Name 'status led' of gpio 'pa0' is not a valid identifier"));
+    assert!(message.contains("Name 'type' of gpio 'pa1' is not a valid identifier"));
+    assert!(message.contains("Name 'led' of gpio 'pc14' is already used by gpio 'pc13'"));
+    assert!(message.contains("Name 'clocks' of gpio 'pc15' is already used by the init code"));
+    assert!(message.contains("Name 'pb6' of serial gpio 'pb6' is already used by pwm gpio 'pa2'"));
+    assert!(!message.contains("debug"));
 }
@@ -0,0 +1,238 @@
+//! The alternate function pin mappings of the stm32f1 peripherals.
+//! Timers and usarts can be routed to different gpios by remapping them
+//! in the AFIO_MAPR register (see reference manual RM0008, section 9.3).
+//! The hal encodes the remap for timers as a type parameter of `Timer::pwm`
+//! and for usarts in the pin types passed to the serial constructor.
+use super::{gpio_name, Channel, Pin, Port, SerialID, TimerID};
+use crate::device::PeripheralId;
+
+/// The gpios of the four output compare channels for one timer remap
+struct TimerRemap {
+    /// The remap type in `stm32f1xx_hal::timer`
+    name: &'static str,
+    channels: [(Port, usize); 4],
+}
+
+/// The transmit and receive gpios for one usart remap
+struct SerialRemap {
+    name: &'static str,
+    tx: (Port, usize),
+    rx: (Port, usize),
+}
+
+const TIM1_REMAPS: &[TimerRemap] = &[
+    TimerRemap {
+        name: "Tim1NoRemap",
+        channels: [(Port::A, 8), (Port::A, 9), (Port::A, 10), (Port::A, 11)],
+    },
+    TimerRemap {
+        name: "Tim1FullRemap",
+        channels: [(Port::E, 9), (Port::E, 11), (Port::E, 13), (Port::E, 14)],
+    },
+];
+const TIM2_REMAPS: &[TimerRemap] = &[
+    TimerRemap {
+        name: "Tim2NoRemap",
+        channels: [(Port::A, 0), (Port::A, 1), (Port::A, 2), (Port::A, 3)],
+    },
+    TimerRemap {
+        name: "Tim2PartialRemap1",
+        channels: [(Port::A, 15), (Port::B, 3), (Port::A, 2), (Port::A, 3)],
+    },
+    TimerRemap {
+        name: "Tim2PartialRemap2",
+        channels: [(Port::A, 0), (Port::A, 1), (Port::B, 10), (Port::B, 11)],
+    },
+    TimerRemap {
+        name: "Tim2FullRemap",
+        channels: [(Port::A, 15), (Port::B, 3), (Port::B, 10), (Port::B, 11)],
+    },
+];
+const TIM3_REMAPS: &[TimerRemap] = &[
+    TimerRemap {
+        name: "Tim3NoRemap",
+        channels: [(Port::A, 6), (Port::A, 7), (Port::B, 0), (Port::B, 1)],
+    },
+    TimerRemap {
+        name: "Tim3PartialRemap",
+        channels: [(Port::B, 4), (Port::B, 5), (Port::B, 0), (Port::B, 1)],
+    },
+    TimerRemap {
+        name: "Tim3FullRemap",
+        channels: [(Port::C, 6), (Port::C, 7), (Port::C, 8), (Port::C, 9)],
+    },
+];
+const TIM4_REMAPS: &[TimerRemap] = &[
+    TimerRemap {
+        name: "Tim4NoRemap",
+        channels: [(Port::B, 6), (Port::B, 7), (Port::B, 8), (Port::B, 9)],
+    },
+    TimerRemap {
+        name: "Tim4Remap",
+        channels: [(Port::D, 12), (Port::D, 13), (Port::D, 14), (Port::D, 15)],
+    },
+];
+
+const USART1_REMAPS: &[SerialRemap] = &[
+    SerialRemap {
+        name: "no remap",
+        tx: (Port::A, 9),
+        rx: (Port::A, 10),
+    },
+    SerialRemap {
+        name: "remap",
+        tx: (Port::B, 6),
+        rx: (Port::B, 7),
+    },
+];
+const USART2_REMAPS: &[SerialRemap] = &[
+    SerialRemap {
+        name: "no remap",
+        tx: (Port::A, 2),
+        rx: (Port::A, 3),
+    },
+    SerialRemap {
+        name: "remap",
+        tx: (Port::D, 5),
+        rx: (Port::D, 6),
+    },
+];
+const USART3_REMAPS: &[SerialRemap] = &[
+    SerialRemap {
+        name: "no remap",
+        tx: (Port::B, 10),
+        rx: (Port::B, 11),
+    },
+    SerialRemap {
+        name: "partial remap",
+        tx: (Port::C, 10),
+        rx: (Port::C, 11),
+    },
+    SerialRemap {
+        name: "full remap",
+        tx: (Port::D, 8),
+        rx: (Port::D, 9),
+    },
+];
+
+const CHANNELS: [Channel; 4] = [Channel::C1, Channel::C2, Channel::C3, Channel::C4];
+
+fn pin_name((port, pin): (Port, usize)) -> String {
+    gpio_name(Pin(pin), port)
+}
+
+impl TimerID {
+    fn remaps(&self) -> &'static [TimerRemap] {
+        match self {
+            TimerID::Tim1 => TIM1_REMAPS,
+            TimerID::Tim2 => TIM2_REMAPS,
+            TimerID::Tim3 => TIM3_REMAPS,
+            TimerID::Tim4 => TIM4_REMAPS,
+        }
+    }
+}
+
+impl SerialID {
+    fn remaps(&self) -> &'static [SerialRemap] {
+        match self {
+            SerialID::Usart1 => USART1_REMAPS,
+            SerialID::Usart2 => USART2_REMAPS,
+            SerialID::Usart3 => USART3_REMAPS,
+        }
+    }
+}
+
+/// Finds the first remap of the timer that routes a channel to each of the pins.
+/// Returns the name of the hal remap type and the channel of each pin.
+pub(super) fn timer_remap(
+    timer: TimerID,
+    pins: &[(Pin, Port)],
+) -> Result<(&'static str, Vec<Channel>), String> {
+    'remaps: for remap in timer.remaps() {
+        let mut channels = Vec::with_capacity(pins.len());
+        for (pin, port) in pins {
+            match remap
+                .channels
+                .iter()
+                .position(|gpio| *gpio == (*port, pin.0))
+            {
+                Some(index) => channels.push(CHANNELS[index]),
+                None => continue 'remaps,
+            }
+        }
+        return Ok((remap.name, channels));
+    }
+    let mut message = format!(
+        "Gpios ({}) cannot be mapped on the channels of timer '{}'. Legal pins are:",
+        pins.iter()
+            .map(|(pin, port)| pin_name((*port, pin.0)))
+            .collect::<Vec<String>>()
+            .join(", "),
+        timer.lower()
+    );
+    for remap in timer.remaps() {
+        message.push_str(&format!(
+            "\n    {}: {}",
+            remap.name,
+            CHANNELS
+                .iter()
+                .zip(remap.channels.iter())
+                .map(|(channel, gpio)| format!("{} {}", channel.name(), pin_name(*gpio)))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+    Err(message)
+}
+
+/// Checks that the usart can be routed to the tx and rx pins.
+/// The hal derives the actual remap from the pin types, so only the name
+/// of the remap is returned.
+pub(super) fn serial_remap(
+    serial: SerialID,
+    tx: (Pin, Port),
+    rx: (Pin, Port),
+) -> Result<&'static str, String> {
+    let tx = (tx.1, tx.0 .0);
+    let rx = (rx.1, rx.0 .0);
+    if let Some(remap) = serial
+        .remaps()
+        .iter()
+        .find(|remap| remap.tx == tx && remap.rx == rx)
+    {
+        return Ok(remap.name);
+    }
+    let mut message = format!(
+        "Gpios tx: {}, rx: {} cannot be mapped on '{}'. Legal pins are:",
+        pin_name(tx),
+        pin_name(rx),
+        serial.lower()
+    );
+    for remap in serial.remaps() {
+        message.push_str(&format!(
+            "\n    {}: tx: {}, rx: {}",
+            remap.name,
+            pin_name(remap.tx),
+            pin_name(remap.rx)
+        ));
+    }
+    Err(message)
+}
+
+#[test]
+fn remap_test() {
+    assert_eq!(
+        timer_remap(TimerID::Tim2, &[(Pin(15), Port::A), (Pin(11), Port::B)]),
+        Ok(("Tim2FullRemap", vec![Channel::C1, Channel::C4]))
+    );
+    assert_eq!(
+        timer_remap(TimerID::Tim2, &[(Pin(2), Port::A)]),
+        Ok(("Tim2NoRemap", vec![Channel::C3]))
+    );
+    assert!(timer_remap(TimerID::Tim3, &[(Pin(6), Port::A), (Pin(6), Port::C)]).is_err());
+    assert_eq!(
+        serial_remap(SerialID::Usart1, (Pin(6), Port::B), (Pin(7), Port::B)),
+        Ok("remap")
+    );
+    assert!(serial_remap(SerialID::Usart1, (Pin(9), Port::A), (Pin(7), Port::B)).is_err());
+}
@@ -0,0 +1,148 @@
+//! The alternate function mappings of the stm32f401 and stm32f411 pins
+//! (see datasheets DS9716 and DS10314, table 9).
+//! Each gpio is switched to the alternate function of its peripheral
+//! itself, the hal checks the function number in the pin types.
+use super::{gpio_name, Channel, Pin, Port, SerialID, TimerID};
+use crate::device::PeripheralId;
+
+/// The output compare channels of the timers, with their gpios and the
+/// alternate function that connects them
+const TIMER_PINS: &[(TimerID, Channel, Port, usize, u8)] = &[
+    (TimerID::Tim1, Channel::C1, Port::A, 8, 1),
+    (TimerID::Tim1, Channel::C1, Port::E, 9, 1),
+    (TimerID::Tim1, Channel::C2, Port::A, 9, 1),
+    (TimerID::Tim1, Channel::C2, Port::E, 11, 1),
+    (TimerID::Tim1, Channel::C3, Port::A, 10, 1),
+    (TimerID::Tim1, Channel::C3, Port::E, 13, 1),
+    (TimerID::Tim1, Channel::C4, Port::A, 11, 1),
+    (TimerID::Tim1, Channel::C4, Port::E, 14, 1),
+    (TimerID::Tim2, Channel::C1, Port::A, 0, 1),
+    (TimerID::Tim2, Channel::C1, Port::A, 5, 1),
+    (TimerID::Tim2, Channel::C1, Port::A, 15, 1),
+    (TimerID::Tim2, Channel::C2, Port::A, 1, 1),
+    (TimerID::Tim2, Channel::C2, Port::B, 3, 1),
+    (TimerID::Tim2, Channel::C3, Port::A, 2, 1),
+    (TimerID::Tim2, Channel::C3, Port::B, 10, 1),
+    (TimerID::Tim2, Channel::C4, Port::A, 3, 1),
+    (TimerID::Tim3, Channel::C1, Port::A, 6, 2),
+    (TimerID::Tim3, Channel::C1, Port::B, 4, 2),
+    (TimerID::Tim3, Channel::C1, Port::C, 6, 2),
+    (TimerID::Tim3, Channel::C2, Port::A, 7, 2),
+    (TimerID::Tim3, Channel::C2, Port::B, 5, 2),
+    (TimerID::Tim3, Channel::C2, Port::C, 7, 2),
+    (TimerID::Tim3, Channel::C3, Port::B, 0, 2),
+    (TimerID::Tim3, Channel::C3, Port::C, 8, 2),
+    (TimerID::Tim3, Channel::C4, Port::B, 1, 2),
+    (TimerID::Tim3, Channel::C4, Port::C, 9, 2),
+    (TimerID::Tim4, Channel::C1, Port::B, 6, 2),
+    (TimerID::Tim4, Channel::C1, Port::D, 12, 2),
+    (TimerID::Tim4, Channel::C2, Port::B, 7, 2),
+    (TimerID::Tim4, Channel::C2, Port::D, 13, 2),
+    (TimerID::Tim4, Channel::C3, Port::B, 8, 2),
+    (TimerID::Tim4, Channel::C3, Port::D, 14, 2),
+    (TimerID::Tim4, Channel::C4, Port::B, 9, 2),
+    (TimerID::Tim4, Channel::C4, Port::D, 15, 2),
+    (TimerID::Tim5, Channel::C1, Port::A, 0, 2),
+    (TimerID::Tim5, Channel::C2, Port::A, 1, 2),
+    (TimerID::Tim5, Channel::C3, Port::A, 2, 2),
+    (TimerID::Tim5, Channel::C4, Port::A, 3, 2),
+    (TimerID::Tim9, Channel::C1, Port::A, 2, 3),
+    (TimerID::Tim9, Channel::C1, Port::E, 5, 3),
+    (TimerID::Tim9, Channel::C2, Port::A, 3, 3),
+    (TimerID::Tim9, Channel::C2, Port::E, 6, 3),
+    (TimerID::Tim10, Channel::C1, Port::B, 8, 3),
+    (TimerID::Tim11, Channel::C1, Port::B, 9, 3),
+];
+
+/// The transmit and receive gpios of the usarts with the alternate function
+/// that connects them. Transmit and receive gpios can be combined freely.
+const SERIAL_TX_PINS: &[(SerialID, Port, usize, u8)] = &[
+    (SerialID::Usart1, Port::A, 9, 7),
+    (SerialID::Usart1, Port::B, 6, 7),
+    (SerialID::Usart2, Port::A, 2, 7),
+    (SerialID::Usart2, Port::D, 5, 7),
+    (SerialID::Usart6, Port::A, 11, 8),
+    (SerialID::Usart6, Port::C, 6, 8),
+];
+const SERIAL_RX_PINS: &[(SerialID, Port, usize, u8)] = &[
+    (SerialID::Usart1, Port::A, 10, 7),
+    (SerialID::Usart1, Port::B, 7, 7),
+    (SerialID::Usart2, Port::A, 3, 7),
+    (SerialID::Usart2, Port::D, 6, 7),
+    (SerialID::Usart6, Port::A, 12, 8),
+    (SerialID::Usart6, Port::C, 7, 8),
+];
+
+fn pin_names(pins: impl Iterator<Item = (Port, usize)>) -> String {
+    pins.map(|(port, pin)| gpio_name(Pin(pin), port))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The channel of the timer on the gpio and its alternate function
+pub(super) fn timer_channel(timer: TimerID, pin: Pin, port: Port) -> Result<(Channel, u8), String> {
+    TIMER_PINS
+        .iter()
+        .find(|(id, _, p, n, _)| *id == timer && *p == port && *n == pin.0)
+        .map(|(_, channel, _, _, function)| (*channel, *function))
+        .ok_or_else(|| {
+            format!(
+                "Gpio '{}' is not a channel of timer '{}' (possible gpios: {})",
+                gpio_name(pin, port),
+                timer.lower(),
+                pin_names(
+                    TIMER_PINS
+                        .iter()
+                        .filter(|(id, ..)| *id == timer)
+                        .map(|(_, _, port, pin, _)| (*port, *pin))
+                )
+            )
+        })
+}
+
+/// The alternate functions of the transmit and receive gpios of the usart
+pub(super) fn serial_functions(
+    serial: SerialID,
+    tx: (Pin, Port),
+    rx: (Pin, Port),
+) -> Result<(u8, u8), String> {
+    let function = |pins: &[(SerialID, Port, usize, u8)], (pin, port): (Pin, Port), half: &str| {
+        pins.iter()
+            .find(|(id, p, n, _)| *id == serial && *p == port && *n == pin.0)
+            .map(|(.., function)| *function)
+            .ok_or_else(|| {
+                format!(
+                    "Gpio '{}' cannot be the {} gpio of serial '{}' (possible gpios: {})",
+                    gpio_name(pin, port),
+                    half,
+                    serial.lower(),
+                    pin_names(
+                        pins.iter()
+                            .filter(|(id, ..)| *id == serial)
+                            .map(|(_, port, pin, _)| (*port, *pin))
+                    )
+                )
+            })
+    };
+    Ok((
+        function(SERIAL_TX_PINS, tx, "tx")?,
+        function(SERIAL_RX_PINS, rx, "rx")?,
+    ))
+}
+
+#[test]
+fn alternate_test() {
+    assert_eq!(
+        timer_channel(TimerID::Tim3, Pin(5), Port::B),
+        Ok((Channel::C2, 2))
+    );
+    assert_eq!(
+        timer_channel(TimerID::Tim10, Pin(9), Port::B),
+        Err("Gpio 'pb9' is not a channel of timer 'tim10' (possible gpios: pb8)".to_string())
+    );
+    assert_eq!(
+        serial_functions(SerialID::Usart6, (Pin(11), Port::A), (Pin(7), Port::C)),
+        Ok((8, 8))
+    );
+    assert!(serial_functions(SerialID::Usart2, (Pin(9), Port::A), (Pin(3), Port::A)).is_err());
+}
@@ -0,0 +1,289 @@
+//! The clock tree of the stm32f401 and stm32f411 (see reference manuals
+//! RM0368 and RM0383, section 6.2).
+//! Like the stm32f1 hal, the stm32f4 hal derives the pll and the bus
+//! prescalers from the requested frequencies in `CFGR::freeze`. The same
+//! derivation is done here to report the configured frequencies and to
+//! reject impossible configurations at expansion time.
+use crate::device::{Clocks, Hertz};
+
+/// Frequency of the internal oscillator
+const HSI: Hertz = Hertz(16_000_000);
+const HSE_MIN: Hertz = Hertz(4_000_000);
+const HSE_MAX: Hertz = Hertz(26_000_000);
+/// The pll multiplies its input into this range before dividing it
+/// down to the system clock
+const VCO_MIN: Hertz = Hertz(100_000_000);
+const VCO_MAX: Hertz = Hertz(432_000_000);
+
+/// The parts of the family differ in their maximum frequencies
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(super) enum Part {
+    Stm32f401,
+    Stm32f411,
+}
+
+impl Part {
+    /// The generic 'stm32f4xx' kind is checked against the slower stm32f401
+    pub fn from_kind(kind: &str) -> Self {
+        match kind {
+            "stm32f411" => Part::Stm32f411,
+            _ => Part::Stm32f401,
+        }
+    }
+    fn sysclk_max(&self) -> Hertz {
+        match self {
+            Part::Stm32f401 => Hertz(84_000_000),
+            Part::Stm32f411 => Hertz(100_000_000),
+        }
+    }
+    fn pclk1_max(&self) -> Hertz {
+        Hertz(self.sysclk_max().0 / 2)
+    }
+}
+
+/// The frequencies the hal configures for the requested clocks
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(super) struct ClockTree {
+    /// The frequencies from the yaml, the hal derives the tree from them
+    pub requested: Clocks,
+    part: Part,
+    /// Output frequency of the voltage controlled oscillator of the pll
+    vco: Option<Hertz>,
+    pub sysclk: Hertz,
+    pub hclk: Hertz,
+    pub pclk1: Hertz,
+    pub pclk2: Hertz,
+    ppre1: usize,
+    ppre2: usize,
+    /// Wait states of the flash for the system clock at 3.3V
+    pub flash_latency: u8,
+}
+
+impl ClockTree {
+    /// Mirrors the pll and prescaler selection of
+    /// `stm32f4xx_hal::rcc::CFGR::freeze`. The hal rounds down to the next
+    /// reachable frequency, unreachable requests are reported by
+    /// [`ClockTree::check`].
+    pub fn solve(requested: Clocks, part: Part) -> Self {
+        let pllsrcclk = requested.hse.unwrap_or(HSI).0;
+        // a crystal out of range is reported by the check, the pll search
+        // would divide by its frequency
+        let pll_input = (HSE_MIN.0..=HSE_MAX.0).contains(&pllsrcclk);
+        let (sysclk, vco) = match requested.sysclk {
+            Some(sysclk) if pll_input && sysclk.0 != pllsrcclk && sysclk.0 != 0 => {
+                let sysclk = sysclk.0;
+                // the output divider is one of 2, 4, 6 or 8
+                let sysclk_div = (VCO_MAX.0 / sysclk).clamp(2, 8) & !1;
+                // the input divider brings the pll input to 1 to 2 mhz
+                let pllm_min = pllsrcclk.div_ceil(2_000_000).max(1);
+                let pllm_max = (pllsrcclk / 1_000_000).max(pllm_min);
+                let pllm = (pllm_min..=pllm_max)
+                    .min_by_key(|pllm| {
+                        let vco_in = pllsrcclk / pllm;
+                        let plln = sysclk * sysclk_div / vco_in;
+                        sysclk - vco_in * plln / sysclk_div
+                    })
+                    .unwrap_or(pllm_min);
+                let vco_in = pllsrcclk / pllm;
+                let plln = sysclk * sysclk_div / vco_in;
+                (
+                    Hertz(vco_in * plln / sysclk_div),
+                    Some(Hertz(vco_in * plln)),
+                )
+            }
+            _ => (Hertz(pllsrcclk), None),
+        };
+
+        let hpre = match requested.hclk.map(|hclk| sysclk.0 / hclk.0.max(1)) {
+            None | Some(0..=1) => 1,
+            Some(2) => 2,
+            Some(3..=5) => 4,
+            Some(6..=11) => 8,
+            Some(12..=39) => 16,
+            Some(40..=95) => 64,
+            Some(96..=191) => 128,
+            Some(192..=383) => 256,
+            Some(_) => 512,
+        };
+        let hclk = Hertz(sysclk.0 / hpre);
+
+        // unlike the stm32f1 hal, the bus clocks default to their maximum
+        let apb_prescaler = |pclk: Hertz| match hclk.0 / pclk.0.max(1) {
+            0..=1 => 1,
+            2 => 2,
+            3..=5 => 4,
+            6..=11 => 8,
+            _ => 16,
+        };
+        let ppre1 = apb_prescaler(
+            requested
+                .pclk1
+                .unwrap_or_else(|| Hertz(hclk.0.min(part.pclk1_max().0))),
+        );
+        let ppre2 = apb_prescaler(
+            requested
+                .pclk2
+                .unwrap_or_else(|| Hertz(hclk.0.min(part.sysclk_max().0))),
+        );
+        let pclk1 = Hertz(hclk.0 / ppre1);
+        let pclk2 = Hertz(hclk.0 / ppre2);
+
+        let flash_latency = (sysclk.0.saturating_sub(1) / 30_000_000) as u8;
+
+        Self {
+            requested,
+            part,
+            vco,
+            sysclk,
+            hclk,
+            pclk1,
+            pclk2,
+            ppre1,
+            ppre2,
+            flash_latency,
+        }
+    }
+    /// Reports requested frequencies that the hal cannot configure and
+    /// derived frequencies that exceed the hardware limits.
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(hse) = self.requested.hse {
+            if hse < HSE_MIN || hse > HSE_MAX {
+                errors.push(format!(
+                    "Clock 'hse' of {} is not between {} and {}",
+                    hse, HSE_MIN, HSE_MAX
+                ));
+            }
+        }
+        if self.requested.adcclk.is_some() {
+            errors.push("Clock 'adcclk' is set by the adc driver on the stm32f4xx".to_string());
+        }
+        if let Some(vco) = self.vco {
+            if vco < VCO_MIN || vco > VCO_MAX {
+                errors.push(format!(
+                    "Clock 'sysclk' of {} cannot be generated by the pll",
+                    self.requested.sysclk.unwrap_or(self.sysclk)
+                ));
+            }
+        }
+        let clocks = [
+            (
+                "sysclk",
+                self.requested.sysclk,
+                self.sysclk,
+                self.part.sysclk_max(),
+            ),
+            (
+                "hclk",
+                self.requested.hclk,
+                self.hclk,
+                self.part.sysclk_max(),
+            ),
+            (
+                "pclk1",
+                self.requested.pclk1,
+                self.pclk1,
+                self.part.pclk1_max(),
+            ),
+            (
+                "pclk2",
+                self.requested.pclk2,
+                self.pclk2,
+                self.part.sysclk_max(),
+            ),
+        ];
+        for (name, requested, achieved, max) in clocks.iter() {
+            match requested {
+                Some(requested) if requested > max => errors.push(format!(
+                    "Clock '{}' of {} exceeds the maximum of {}",
+                    name, requested, max
+                )),
+                Some(requested) if achieved > requested => errors.push(format!(
+                    "Clock '{}' of {} cannot be reached, the lowest possible frequency is {}",
+                    name, requested, achieved
+                )),
+                _ => {}
+            }
+        }
+        errors
+    }
+    /// The frequency of the peripheral bus ("apb1" or "apb2")
+    pub fn bus_clock(&self, bus: &str) -> Hertz {
+        match bus {
+            "apb1" => self.pclk1,
+            "apb2" => self.pclk2,
+            other => unreachable!("unknown peripheral bus {}", other),
+        }
+    }
+    /// The timers run with twice the bus frequency if the bus is prescaled
+    pub fn timer_clock(&self, bus: &str) -> Hertz {
+        let prescaler = match bus {
+            "apb1" => self.ppre1,
+            "apb2" => self.ppre2,
+            other => unreachable!("unknown peripheral bus {}", other),
+        };
+        match prescaler {
+            1 => self.bus_clock(bus),
+            _ => Hertz(self.bus_clock(bus).0 * 2),
+        }
+    }
+}
+
+#[test]
+fn clock_tree_test() {
+    let tree = ClockTree::solve(
+        Clocks {
+            hse: Some(Hertz(25_000_000)),
+            sysclk: Some(Hertz(84_000_000)),
+            ..Clocks::default()
+        },
+        Part::Stm32f401,
+    );
+    assert!(tree.check().is_empty());
+    assert_eq!(tree.sysclk, Hertz(84_000_000));
+    assert_eq!(tree.pclk1, Hertz(42_000_000));
+    assert_eq!(tree.pclk2, Hertz(84_000_000));
+    assert_eq!(tree.timer_clock("apb1"), Hertz(84_000_000));
+    assert_eq!(tree.flash_latency, 2);
+
+    // the internal oscillator without pll
+    let tree = ClockTree::solve(Clocks::default(), Part::Stm32f401);
+    assert!(tree.check().is_empty());
+    assert_eq!(tree.sysclk, Hertz(16_000_000));
+    assert_eq!(tree.pclk1, Hertz(16_000_000));
+    assert_eq!(tree.flash_latency, 0);
+
+    // the pll is not searched for a crystal out of range
+    let tree = ClockTree::solve(
+        Clocks {
+            hse: Some(Hertz(0)),
+            sysclk: Some(Hertz(84_000_000)),
+            ..Clocks::default()
+        },
+        Part::Stm32f401,
+    );
+    assert!(tree
+        .check()
+        .contains(&"Clock 'hse' of 0hz is not between 4mhz and 26mhz".to_string()));
+
+    let clocks = Clocks {
+        sysclk: Some(Hertz(100_000_000)),
+        ..Clocks::default()
+    };
+    assert!(ClockTree::solve(clocks, Part::Stm32f411).check().is_empty());
+    assert_eq!(
+        ClockTree::solve(clocks, Part::Stm32f401).check(),
+        vec!["Clock 'sysclk' of 100mhz exceeds the maximum of 84mhz"]
+    );
+    let tree = ClockTree::solve(
+        Clocks {
+            sysclk: Some(Hertz(8_000_000)),
+            ..Clocks::default()
+        },
+        Part::Stm32f401,
+    );
+    assert_eq!(
+        tree.check(),
+        vec!["Clock 'sysclk' of 8mhz cannot be generated by the pll"]
+    );
+}
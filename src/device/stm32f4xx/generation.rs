@@ -0,0 +1,443 @@
+use std::collections::{BTreeSet, HashSet};
+
+use quote::format_ident;
+use syn::{parse_quote, parse_str, Ident, Item, ItemStruct, Stmt};
+
+use crate::device::{
+    generation::{
+        fields, frequency_consts, nvic_unmasks, static_init_and_return, ComponentGroups,
+        Initialized,
+    },
+    stm32::generation::{
+        gpio_ident, gpio_short_type, init_function_name, initial_duty, interrupt_source, pwm_pins,
+    },
+    used_gpios, Clocks, FrameLength, InterruptMode, PeripheralId, PinMode, DEFAULT_PWM_FREQUENCY,
+};
+
+use super::{
+    alternate, Channel, GpioPort, Peripheral, Pin, Port, Stm32f4xxPeripherals, NVIC_PRIO_BITS,
+};
+
+fn gpio_type(pin: Pin, port: Port, mode: PinMode) -> syn::Type {
+    let mode = match mode {
+        PinMode::InputFloating => "Input<stm32f4xx_hal::gpio::Floating>",
+        PinMode::InputPullUp => "Input<stm32f4xx_hal::gpio::PullUp>",
+        PinMode::InputPullDown => "Input<stm32f4xx_hal::gpio::PullDown>",
+        PinMode::OutputPushPull => "Output<stm32f4xx_hal::gpio::PushPull>",
+        PinMode::OutputOpenDrain => "Output<stm32f4xx_hal::gpio::OpenDrain>",
+    };
+    parse_str(&format!(
+        "stm32f4xx_hal::gpio::{}::{}<stm32f4xx_hal::gpio::{}>",
+        port.lower(),
+        gpio_short_type(pin, port),
+        mode
+    ))
+    .unwrap()
+}
+
+/// The variant of `Edge` in the hal
+fn edge_name(mode: InterruptMode) -> &'static str {
+    match mode {
+        InterruptMode::None => {
+            panic!("InterruptMode::None cannot be converted into an identifier")
+        }
+        InterruptMode::Rising => "Rising",
+        InterruptMode::Falling => "Falling",
+        InterruptMode::RisingFalling => "RisingFalling",
+    }
+}
+
+/// The function of the serial config that sets the frame length
+fn word_length_function_name(frame_length: FrameLength) -> &'static str {
+    match frame_length {
+        FrameLength::Bits8 => "wordlength_8",
+        FrameLength::Bits9 => "wordlength_9",
+    }
+}
+
+/// Unlike the stm32f1 hal, the stm32f4 hal does not need the bus
+/// registers of the rcc to initialize peripherals, so the only state
+/// is the pool of gpios that are not yet brought into a mode.
+pub struct DeviceInit {
+    init_block: Vec<syn::Stmt>,
+    peripherals: Ident,
+    clocks: Ident,
+    syscfg: Option<Ident>,
+    gpios: HashSet<Ident>,
+}
+
+impl DeviceInit {
+    /// Freezes the clocks and splits the ports of all used gpios
+    fn new(peripheral_config: &Stm32f4xxPeripherals) -> Self {
+        let peripherals = format_ident!("peripherals");
+        let clocks = format_ident!("clocks");
+        let mut init_block: Vec<Stmt> = parse_quote!(
+            use stm32f4xx_hal::prelude::*;
+            use stm32f4xx_hal::gpio::ExtiPin;
+            let mut #peripherals = stm32f4xx_hal::pac::Peripherals::take().unwrap();
+            let rcc = #peripherals.RCC.constrain();
+        );
+        // expand: let clocks = rcc.cfgr.use_hse(f.hz()).sysclk(f.hz())...freeze();
+        let requested: Clocks = peripheral_config.clocks.requested;
+        let settings = [
+            ("use_hse", requested.hse),
+            ("sysclk", requested.sysclk),
+            ("hclk", requested.hclk),
+            ("pclk1", requested.pclk1),
+            ("pclk2", requested.pclk2),
+        ];
+        let mut cfgr: syn::Expr = parse_quote!(rcc.cfgr);
+        for (function, frequency) in settings.iter() {
+            if let Some(frequency) = frequency {
+                let function = format_ident!("{}", function);
+                let frequency = frequency.0 as u32;
+                cfgr = parse_quote!(#cfgr.#function(#frequency.hz()));
+            }
+        }
+        init_block.push(parse_quote!(let #clocks = #cfgr.freeze();));
+        // expand: let gpiox = peripherals.GPIOX.split();
+        let gpios = used_gpios(&peripheral_config.components());
+        let ports: BTreeSet<&Port> = gpios.iter().map(|(_, port)| port).collect();
+        for port in ports {
+            let port_lower = format_ident!("{}", port.lower());
+            let port_upper = format_ident!("{}", port.upper());
+            init_block.push(parse_quote!(
+                let #port_lower = #peripherals.#port_upper.split();
+            ));
+        }
+        Self {
+            init_block,
+            peripherals,
+            clocks,
+            syscfg: None,
+            gpios: gpios
+                .into_iter()
+                .map(|(pin, port)| gpio_ident(pin, port))
+                .collect(),
+        }
+    }
+    fn syscfg(&mut self) -> Ident {
+        if self.syscfg.is_none() {
+            let peripherals_ident = &self.peripherals;
+            let syscfg_ident = format_ident!("syscfg");
+            self.init_block.push(parse_quote!(
+                let mut #syscfg_ident = #peripherals_ident.SYSCFG.constrain();
+            ));
+            self.syscfg = Some(syscfg_ident);
+        }
+        self.syscfg.as_ref().unwrap().clone()
+    }
+    /// Takes the gpio from the pool and brings it into a mode with the init function.
+    /// The binding is named after the gpio if no other name is given.
+    /// expand: let mut binding = gpiox.pxy.into_mode();
+    fn init_gpio(
+        &mut self,
+        (pin, port): (Pin, Port),
+        init_function: syn::Path,
+        binding: Option<&str>,
+    ) -> Ident {
+        let port_ident = format_ident!("{}", port.lower());
+        let pin_name = self
+            .gpios
+            .take(&gpio_ident(pin, port))
+            .expect("Use of uninitialized gpio");
+        let gpio_ident = match binding {
+            Some(binding) => format_ident!("{}", binding),
+            None => pin_name.clone(),
+        };
+        self.init_block.push(parse_quote!(
+            let mut #gpio_ident = #port_ident.#pin_name.#init_function();
+        ));
+        gpio_ident
+    }
+    fn init_mode(&mut self, pin: (Pin, Port), mode: PinMode, binding: Option<&str>) -> Ident {
+        let init_function = format_ident!("{}", init_function_name(mode));
+        self.init_gpio(pin, parse_quote!(#init_function), binding)
+    }
+    /// expand: let mut binding = gpiox.pxy.into_alternate::<function>();
+    fn init_alternate(&mut self, pin: (Pin, Port), function: u8, binding: Option<&str>) -> Ident {
+        self.init_gpio(pin, parse_quote!(into_alternate::<#function>), binding)
+    }
+    fn inputs(&mut self, peripheral_config: &Stm32f4xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for gpio in &peripheral_config.gpio.input {
+            let gpio_ident = self.init_mode(gpio.pin, gpio.mode, Some(&gpio.binding()));
+            match gpio.interrupt_mode {
+                InterruptMode::None => {}
+                other => {
+                    let edge_ident = format_ident!("{}", edge_name(other));
+                    let syscfg_ident = self.syscfg();
+                    let peripherals_ident = &self.peripherals;
+                    self.init_block.append(&mut interrupt_source(
+                        &gpio_ident,
+                        &syscfg_ident,
+                        &parse_quote!(&mut #peripherals_ident.EXTI),
+                        &parse_quote!(stm32f4xx_hal::gpio::Edge::#edge_ident),
+                    ));
+                }
+            }
+            let (pin, port) = gpio.pin;
+            idents.push(Initialized {
+                ty: gpio_type(pin, port, gpio.mode),
+                ident: gpio_ident,
+            });
+        }
+        idents
+    }
+    fn outputs(&mut self, peripheral_config: &Stm32f4xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for gpio in &peripheral_config.gpio.output {
+            let gpio_ident = self.init_mode(gpio.pin, gpio.mode, Some(&gpio.binding()));
+            let (pin, port) = gpio.pin;
+            idents.push(Initialized {
+                ty: gpio_type(pin, port, gpio.mode),
+                ident: gpio_ident,
+            });
+        }
+        idents
+    }
+
+    fn timers(&mut self, peripheral_config: &Stm32f4xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        let clocks_ident = &self.clocks;
+        let peripherals_ident = &self.peripherals;
+        for timer in &peripheral_config.timer {
+            let timer_ident = format_ident!("{}", timer.id.lower());
+            let timer_upper = format_ident!("{}", timer.id.upper());
+            // expand: let timx = Timer::new(peripherals.TIMX, &clocks);
+            self.init_block.push(parse_quote!(
+                let #timer_ident = stm32f4xx_hal::timer::Timer::new(#peripherals_ident.#timer_upper, &#clocks_ident);
+            ));
+            idents.push(Initialized {
+                ty: parse_str(&format!(
+                    "stm32f4xx_hal::timer::Timer<stm32f4xx_hal::pac::{}>",
+                    timer.id.upper()
+                ))
+                .unwrap(),
+                ident: timer_ident,
+            });
+        }
+        idents
+    }
+
+    fn pwms(&mut self, peripheral_config: &Stm32f4xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for pwm in &peripheral_config.pwm {
+            let timer = pwm.timer.id;
+            // the hal expects the pins ordered by channel
+            let mut channels: Vec<(Channel, u8, Pin, Port)> = pwm
+                .pins
+                .iter()
+                .map(|(pin, port)| {
+                    let (channel, function) = alternate::timer_channel(timer, *pin, *port)
+                        .unwrap_or_else(|err| panic!("{}", err));
+                    (channel, function, *pin, *port)
+                })
+                .collect();
+            channels.sort();
+            let pin_idents: Vec<Ident> = channels
+                .iter()
+                .map(|(_, function, pin, port)| {
+                    self.init_alternate((*pin, *port), *function, Some(&pwm.binding(*pin, *port)))
+                })
+                .collect();
+            let clocks_ident = &self.clocks;
+            let peripherals_ident = &self.peripherals;
+            let timer_upper = format_ident!("{}", timer.upper());
+            let frequency = pwm.frequency.unwrap_or(DEFAULT_PWM_FREQUENCY).0 as u32;
+            let (pins, channel_pat) = pwm_pins(&pin_idents);
+            // expand:
+            // let (pxy, ...) = Timer::new(peripherals.TIMX, &clocks).pwm((pxy, ...), freq.hz());
+            self.init_block.push(parse_quote!(
+                let #channel_pat = stm32f4xx_hal::timer::Timer::new(#peripherals_ident.#timer_upper, &#clocks_ident)
+                    .pwm(#pins, #frequency.hz());
+            ));
+            self.init_block
+                .append(&mut initial_duty(&pin_idents, pwm.duty));
+            for ((channel, ..), ident) in channels.into_iter().zip(pin_idents) {
+                idents.push(Initialized {
+                    ty: parse_str(&format!(
+                        "stm32f4xx_hal::pwm::PwmChannel<stm32f4xx_hal::pac::{}, stm32f4xx_hal::pwm::{}>",
+                        timer.upper(),
+                        channel.name()
+                    ))
+                    .unwrap(),
+                    ident,
+                });
+            }
+        }
+        idents
+    }
+
+    fn serials(&mut self, peripheral_config: &Stm32f4xxPeripherals) -> Vec<Initialized> {
+        let mut idents = Vec::new();
+        for serial in &peripheral_config.serial {
+            let (tx_function, rx_function) =
+                alternate::serial_functions(serial.id, serial.tx, serial.rx)
+                    .unwrap_or_else(|err| panic!("{}", err));
+            let tx_pin = self.init_alternate(serial.tx, tx_function, None);
+            let rx_pin = self.init_alternate(serial.rx, rx_function, None);
+            let clocks_ident = &self.clocks;
+            let peripherals_ident = &self.peripherals;
+            let serial_ident = format_ident!("{}", serial.binding());
+            let serial_upper = format_ident!("{}", serial.id.upper());
+            let baud = serial.baud_rate.0;
+            let parity = format_ident!("{}", serial.parity.config_function_name());
+            let stop_bits = format_ident!("{}", serial.stop_bits.ident());
+            let word_length = format_ident!(
+                "{}",
+                word_length_function_name(
+                    serial
+                        .frame_length()
+                        .unwrap_or_else(|err| panic!("{}", err))
+                )
+            );
+            let tx_ident = format_ident!("{}", serial.tx_binding());
+            let rx_ident = format_ident!("{}", serial.rx_binding());
+            // expand:
+            // let mut usartx = Serial::new(peripherals.USARTX, (tx, rx), config, clocks).unwrap();
+            self.init_block.push(parse_quote!(
+                let mut #serial_ident = stm32f4xx_hal::serial::Serial::new(
+                    #peripherals_ident.#serial_upper,
+                    (#tx_pin, #rx_pin),
+                    stm32f4xx_hal::serial::config::Config::default()
+                        .baudrate(#baud.bps())
+                        .#parity()
+                        .stopbits(stm32f4xx_hal::serial::config::StopBits::#stop_bits)
+                        .#word_length(),
+                    #clocks_ident
+                )
+                .unwrap();
+            ));
+            // the serial only listens before it is split
+            if serial.interrupt.rx() {
+                self.init_block.push(parse_quote!(
+                    #serial_ident.listen(stm32f4xx_hal::serial::Event::Rxne);
+                ));
+            }
+            if serial.interrupt.tx() {
+                self.init_block.push(parse_quote!(
+                    #serial_ident.listen(stm32f4xx_hal::serial::Event::Txe);
+                ));
+            }
+            // expand: let (usartx_tx, usartx_rx) = usartx.split();
+            self.init_block.push(parse_quote!(
+                let (mut #tx_ident, mut #rx_ident) = #serial_ident.split();
+            ));
+            for (half, ident) in [("Tx", tx_ident), ("Rx", rx_ident)] {
+                idents.push(Initialized {
+                    ty: parse_str(&format!(
+                        "stm32f4xx_hal::serial::{}<stm32f4xx_hal::pac::{}>",
+                        half,
+                        serial.id.upper()
+                    ))
+                    .unwrap(),
+                    ident,
+                });
+            }
+        }
+        idents
+    }
+
+    pub(crate) fn get_init_block(
+        peripheral_config: &Stm32f4xxPeripherals,
+        item_struct: &ItemStruct,
+    ) -> (Vec<Stmt>, syn::Type, Vec<Item>) {
+        let mut device_init = DeviceInit::new(peripheral_config);
+        let inputs = device_init.inputs(peripheral_config);
+        let outputs = device_init.outputs(peripheral_config);
+        let timer = device_init.timers(peripheral_config);
+        let pwm = device_init.pwms(peripheral_config);
+        let serial = device_init.serials(peripheral_config);
+        static_init_and_return(
+            device_init.init_block,
+            item_struct,
+            ComponentGroups {
+                inputs: fields(&inputs),
+                outputs: fields(&outputs),
+                timers: fields(&timer),
+                pwm: fields(&pwm),
+                serial: fields(&serial),
+            },
+        )
+    }
+}
+
+/// Reports the frequencies the hal configures for the requested clocks,
+/// which may be rounded down from the values in the yaml.
+pub(crate) fn clock_consts(peripheral_config: &Stm32f4xxPeripherals) -> Vec<syn::ImplItem> {
+    let clocks = &peripheral_config.clocks;
+    let mut consts = frequency_consts(&[
+        ("SYSCLK", clocks.sysclk),
+        ("HCLK", clocks.hclk),
+        ("PCLK1", clocks.pclk1),
+        ("PCLK2", clocks.pclk2),
+    ]);
+    let flash_latency = clocks.flash_latency;
+    consts.push(parse_quote!(const FLASH_LATENCY: u8 = #flash_latency;));
+    consts
+}
+
+/// Unmasks the interrupts of all interrupting inputs and serials in the NVIC,
+/// see the stm32f1xx backend.
+pub(crate) fn interrupt_unmasks(peripheral_config: &Stm32f4xxPeripherals) -> Vec<Stmt> {
+    let interrupts = peripheral_config
+        .interrupts()
+        .unwrap_or_else(|errors| panic!("{}", errors[0]));
+    nvic_unmasks(
+        &parse_quote!(stm32f4xx_hal::pac),
+        &interrupts,
+        NVIC_PRIO_BITS,
+    )
+}
+
+#[test]
+fn init_block_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: stm32f401
+clocks:
+  hse: 25mhz
+  sysclk: 84mhz
+gpio:
+  input:
+    - pa0:
+      mode: pull_up
+      interrupt: falling
+pwm:
+  - tim3:
+    pins:
+      - pb5
+      - pa6
+serial:
+  - usart2:
+    tx: pa2
+    rx: pa3
+    baud: 115200
+    interrupt: rx
+",
+    )
+    .unwrap();
+    let config = crate::device::DeviceConfig::from_yaml(&yaml[0]).unwrap();
+    let item_struct: ItemStruct = parse_quote!(
+        struct Board {}
+    );
+    let (init_block, _, _) = config.get_init_fn(&item_struct);
+    let init_block = quote::quote!(#(#init_block)*).to_string();
+    for expected in [
+        quote::quote!(let clocks = rcc.cfgr.use_hse(25000000u32.hz()).sysclk(84000000u32.hz()).freeze();),
+        quote::quote!(pa0.make_interrupt_source(&mut syscfg);),
+        quote::quote!(let mut pa6 = gpioa.pa6.into_alternate::<2u8>();),
+        quote::quote!(let mut pa2 = gpioa.pa2.into_alternate::<7u8>();),
+        quote::quote!(usart2.listen(stm32f4xx_hal::serial::Event::Rxne);),
+        // the channels are ordered by channel
+        quote::quote!(.pwm((pa6, pb5), 1000u32.hz());),
+    ] {
+        assert!(
+            init_block.contains(&expected.to_string()),
+            "{} in {}",
+            expected,
+            init_block
+        );
+    }
+}
@@ -0,0 +1,425 @@
+//! The stm32f401 and stm32f411 family.
+//! Unlike the stm32f1, each gpio selects the alternate function of its
+//! peripheral itself and the external interrupt lines are routed to the
+//! ports in the SYSCFG. The yaml schema is the same as for the stm32f1xx.
+use std::collections::BTreeMap;
+use yaml_rust::Yaml;
+
+mod alternate;
+mod clocks;
+mod generation;
+
+use self::{
+    clocks::{ClockTree, Part},
+    generation::DeviceInit,
+};
+
+use super::{
+    check_components, clocks_yaml, parse_list,
+    stm32::{self, gpio_name, Channel, GpioPort, Peripheral, Pin, NVIC_PRIO_BITS},
+    Clocks, Component, Device, Hertz, PeripheralId, Priority, Tolerance,
+};
+use crate::error::{describe, Error};
+
+#[cfg(test)]
+use super::messages;
+
+type Gpios = super::Gpios<(Pin, Port)>;
+type Serial = super::Serial<SerialID, (Pin, Port)>;
+type Timer = super::Timer<TimerID>;
+#[allow(clippy::upper_case_acronyms)]
+type PWM = stm32::PWM<TimerID, Port>;
+
+/// Bindings of the generated init function that are not components
+const RESERVED_BINDINGS: &[&str] = &[
+    "peripherals",
+    "rcc",
+    "clocks",
+    "syscfg",
+    "gpioa",
+    "gpiob",
+    "gpioc",
+    "gpiod",
+    "gpioe",
+    "gpioh",
+];
+
+#[derive(Debug)]
+pub struct Stm32f4xxPeripherals {
+    gpio: Gpios,
+    timer: Vec<Timer>,
+    pwm: Vec<PWM>,
+    serial: Vec<Serial>,
+    clocks: ClockTree,
+}
+
+impl Device for Stm32f4xxPeripherals {
+    fn kinds() -> &'static [&'static str] {
+        &["stm32f4xx", "stm32f401", "stm32f411"]
+    }
+    /// Parses the peripherals and checks them against the hardware constraints.
+    /// The constraints are only checked if all components could be parsed.
+    fn from_yaml(yaml: &Yaml, clocks: Clocks) -> Result<Self, Vec<Error>> {
+        let part = Part::from_kind(&yaml["kind"].as_str().unwrap_or_default().to_lowercase());
+        let mut errors = Vec::new();
+        let peripherals = Self {
+            gpio: Gpios::from_yaml(&yaml["gpio"], NVIC_PRIO_BITS, &mut errors),
+            timer: parse_list(&yaml["timer"], Timer::from_yaml, &mut errors),
+            pwm: parse_list(&yaml["pwm"], PWM::from_yaml, &mut errors),
+            serial: parse_list(
+                &yaml["serial"],
+                |serial| Serial::from_yaml(serial, NVIC_PRIO_BITS),
+                &mut errors,
+            ),
+            clocks: ClockTree::solve(clocks, part),
+        };
+        if errors.is_empty() {
+            errors = peripherals.check();
+        }
+        for error in peripherals.clocks.check() {
+            errors.push(Error::at(clocks_yaml(yaml), error));
+        }
+        if errors.is_empty() {
+            Ok(peripherals)
+        } else {
+            Err(errors)
+        }
+    }
+    fn init_fn(
+        &self,
+        item_struct: &syn::ItemStruct,
+    ) -> (Vec<syn::Stmt>, syn::Type, Vec<syn::Item>) {
+        DeviceInit::get_init_block(self, item_struct)
+    }
+    fn clock_consts(&self) -> Vec<syn::ImplItem> {
+        generation::clock_consts(self)
+    }
+    fn interrupt_unmasks(&self) -> Vec<syn::Stmt> {
+        generation::interrupt_unmasks(self)
+    }
+}
+
+impl Stm32f4xxPeripherals {
+    /// Checks the timers and serials against the alternate functions of
+    /// their gpios and the interrupting inputs against the EXTI lines.
+    fn check(&self) -> Vec<Error> {
+        let mut errors = stm32::check_peripherals(&self.timer, &self.pwm, &self.serial, |bus| {
+            self.clocks.timer_clock(bus)
+        });
+        for serial in &self.serial {
+            if let Err(err) = alternate::serial_functions(serial.id, serial.tx, serial.rx) {
+                errors.push(Error::new(serial.location, err));
+            }
+            let pclk = self.clocks.bus_clock(serial.id.peripheral_bus());
+            if let Err(err) = serial.baud_rate_divisor(pclk) {
+                errors.push(Error::new(serial.location, err));
+            }
+        }
+        for pwm in &self.pwm {
+            let channels = pwm
+                .pins
+                .iter()
+                .map(|(pin, port)| alternate::timer_channel(pwm.timer.id, *pin, *port))
+                .collect::<Result<Vec<_>, _>>();
+            match channels {
+                Ok(channels) => errors.append(
+                    &mut pwm.check_channels(channels.into_iter().map(|(channel, _)| channel)),
+                ),
+                Err(err) => errors.push(Error::new(pwm.location, err)),
+            }
+        }
+        errors.append(&mut check_components(RESERVED_BINDINGS, &self.components()));
+        if let Err(mut interrupt_errors) = self.interrupts() {
+            errors.append(&mut interrupt_errors);
+        }
+        errors
+    }
+    /// All nvic interrupts of the configured sources and their priority
+    fn interrupts(&self) -> Result<BTreeMap<String, Option<Priority>>, Vec<Error>> {
+        stm32::interrupts(&self.gpio.input, &self.serial)
+    }
+    /// The components in the order of the yaml sections
+    fn components(&self) -> [&dyn Component<(Pin, Port)>; 4] {
+        [&self.gpio, &self.timer, &self.pwm, &self.serial]
+    }
+}
+
+impl Serial {
+    /// The hal rounds the bus clock divided by the baud rate to get the
+    /// value of the baud rate register (BRR) with 16 times oversampling.
+    /// For high baud rates it switches to 8 times oversampling, which
+    /// doubles the divisor. The rounding changes the actual baud rate,
+    /// which has to be within the tolerance of the requested one.
+    fn baud_rate_divisor(&self, pclk: Hertz) -> Result<u32, String> {
+        let baud = self.baud_rate.0 as u64;
+        let pclk_hz = pclk.0 as u64;
+        let clock = if pclk_hz / 16 >= baud {
+            pclk_hz
+        } else if pclk_hz / 8 >= baud {
+            pclk_hz * 2
+        } else {
+            0
+        };
+        let divisor = (clock + baud / 2) / baud;
+        if !(16..=0xFFFF).contains(&divisor) {
+            return Err(format!(
+                "Baud rate {} of serial '{}' cannot be generated from its bus clock of {}",
+                baud,
+                self.id.lower(),
+                pclk
+            ));
+        }
+        let actual = clock / divisor;
+        let deviation = Tolerance::deviation(baud, actual);
+        if deviation > self.baud_tolerance {
+            return Err(format!(
+                "Baud rate {} of serial '{}' is generated as {} from its bus clock of {}, a deviation of {} exceeds the tolerance of {}",
+                baud,
+                self.id.lower(),
+                actual,
+                pclk,
+                deviation,
+                self.baud_tolerance
+            ));
+        }
+        Ok(divisor as u32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum SerialID {
+    Usart1,
+    Usart2,
+    Usart6,
+}
+
+impl PeripheralId for SerialID {
+    const EXAMPLE: &'static str = "usart1";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml.as_str().map(str::to_lowercase).as_deref() {
+            Some("usart1") => Ok(Self::Usart1),
+            Some("usart2") => Ok(Self::Usart2),
+            Some("usart6") => Ok(Self::Usart6),
+            _ => Err(Error::at(
+                yaml,
+                format!(
+                    "Unknown serial {} (usart1, usart2 or usart6)",
+                    describe(yaml)
+                ),
+            )),
+        }
+    }
+    fn lower(&self) -> &str {
+        match self {
+            SerialID::Usart1 => "usart1",
+            SerialID::Usart2 => "usart2",
+            SerialID::Usart6 => "usart6",
+        }
+    }
+}
+
+impl Peripheral for SerialID {
+    fn upper(&self) -> &str {
+        match self {
+            SerialID::Usart1 => "USART1",
+            SerialID::Usart2 => "USART2",
+            SerialID::Usart6 => "USART6",
+        }
+    }
+    fn peripheral_bus(&self) -> &str {
+        match self {
+            SerialID::Usart1 | SerialID::Usart6 => "apb2",
+            SerialID::Usart2 => "apb1",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum TimerID {
+    Tim1,
+    Tim2,
+    Tim3,
+    Tim4,
+    Tim5,
+    Tim9,
+    Tim10,
+    Tim11,
+}
+
+impl PeripheralId for TimerID {
+    const EXAMPLE: &'static str = "tim2";
+    fn from_yaml(yaml: &Yaml) -> Result<Self, Error> {
+        match yaml.as_str().map(str::to_lowercase).as_deref() {
+            Some("tim1") => Ok(TimerID::Tim1),
+            Some("tim2") => Ok(TimerID::Tim2),
+            Some("tim3") => Ok(TimerID::Tim3),
+            Some("tim4") => Ok(TimerID::Tim4),
+            Some("tim5") => Ok(TimerID::Tim5),
+            Some("tim9") => Ok(TimerID::Tim9),
+            Some("tim10") => Ok(TimerID::Tim10),
+            Some("tim11") => Ok(TimerID::Tim11),
+            _ => Err(Error::at(
+                yaml,
+                format!(
+                    "Unknown timer {} (tim1 to tim5, tim9 to tim11)",
+                    describe(yaml)
+                ),
+            )),
+        }
+    }
+    fn lower(&self) -> &str {
+        match self {
+            TimerID::Tim1 => "tim1",
+            TimerID::Tim2 => "tim2",
+            TimerID::Tim3 => "tim3",
+            TimerID::Tim4 => "tim4",
+            TimerID::Tim5 => "tim5",
+            TimerID::Tim9 => "tim9",
+            TimerID::Tim10 => "tim10",
+            TimerID::Tim11 => "tim11",
+        }
+    }
+}
+
+impl Peripheral for TimerID {
+    fn upper(&self) -> &str {
+        match self {
+            TimerID::Tim1 => "TIM1",
+            TimerID::Tim2 => "TIM2",
+            TimerID::Tim3 => "TIM3",
+            TimerID::Tim4 => "TIM4",
+            TimerID::Tim5 => "TIM5",
+            TimerID::Tim9 => "TIM9",
+            TimerID::Tim10 => "TIM10",
+            TimerID::Tim11 => "TIM11",
+        }
+    }
+    /// The advanced timer and the small general purpose timers are clocked
+    /// by the high speed bus, the other timers by the low speed bus.
+    fn peripheral_bus(&self) -> &str {
+        match self {
+            TimerID::Tim1 | TimerID::Tim9 | TimerID::Tim10 | TimerID::Tim11 => "apb2",
+            TimerID::Tim2 | TimerID::Tim3 | TimerID::Tim4 | TimerID::Tim5 => "apb1",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+}
+
+impl GpioPort for Port {
+    const LETTERS: &'static str = "a to e or h";
+    fn from_short(short: char) -> Option<Self> {
+        match short {
+            'a' => Some(Port::A),
+            'b' => Some(Port::B),
+            'c' => Some(Port::C),
+            'd' => Some(Port::D),
+            'e' => Some(Port::E),
+            'h' => Some(Port::H),
+            _ => None,
+        }
+    }
+    fn short(&self) -> char {
+        match self {
+            Port::A => 'a',
+            Port::B => 'b',
+            Port::C => 'c',
+            Port::D => 'd',
+            Port::E => 'e',
+            Port::H => 'h',
+        }
+    }
+    /// Port h only has the oscillator pins ph0 and ph1
+    fn max_pin(&self) -> usize {
+        match self {
+            Port::H => 1,
+            _ => 15,
+        }
+    }
+}
+
+#[test]
+fn check_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+kind: stm32f411
+gpio:
+  input:
+    - pa0:
+      mode: floating
+      interrupt: rising
+    - pb0:
+      mode: floating
+      interrupt: falling
+    - ph2:
+      mode: floating
+    - pa16:
+      mode: floating
+      interrupt: rising
+timer:
+  - tim3
+pwm:
+  - tim3:
+    pins:
+      - pa6
+  - tim4:
+    pins:
+      - pb6
+      - pd12
+serial:
+  - usart6:
+    tx: pa9
+    rx: pc7
+    baud: 9600
+",
+    )
+    .unwrap();
+    let clocks = Clocks {
+        sysclk: Some(Hertz(100_000_000)),
+        ..Clocks::default()
+    };
+    let message = messages(Stm32f4xxPeripherals::from_yaml(&yaml[0], clocks).unwrap_err());
+    assert!(message.contains("Timer 'tim3' is used in 'timer' and in 'pwm'"));
+    assert!(message.contains("Channel 'C1' of timer 'tim4' is used multiple times"));
+    assert!(message.contains("Gpio 'pa9' cannot be the tx gpio of serial 'usart6'"));
+    assert!(message.contains("Gpio 'ph2' does not exist"));
+    assert!(message.contains("Gpio 'pa16' does not exist"));
+    assert!(message.contains("Gpios 'pa0' and 'pb0' both use interrupt line EXTI0"));
+    // the stm32f411 runs at 100mhz
+    assert!(!message.contains("Clock"));
+}
+
+#[test]
+fn baud_rate_test() {
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        "
+- usart2:
+  tx: pa2
+  rx: pa3
+  baud: 115200
+- usart1:
+  tx: pa9
+  rx: pa10
+  baud: 6000000
+",
+    )
+    .unwrap();
+    let serials: Vec<Serial> = yaml[0]
+        .as_vec()
+        .unwrap()
+        .iter()
+        .map(|serial| Serial::from_yaml(serial, NVIC_PRIO_BITS).unwrap())
+        .collect();
+    assert_eq!(serials[0].baud_rate_divisor(Hertz(42_000_000)), Ok(365));
+    // 8 times oversampling
+    assert_eq!(serials[1].baud_rate_divisor(Hertz(84_000_000)), Ok(28));
+    assert!(serials[1].baud_rate_divisor(Hertz(16_000_000)).is_err());
+}
@@ -0,0 +1,292 @@
+//! Errors in the device description.
+//! The parsed yaml does not know where its nodes come from, so an error only
+//! remembers the address of the node that caused it. When the errors are
+//! reported, the addresses are resolved to the line and column of the node
+//! with a table that is built from the events of the yaml parser.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+    Yaml,
+};
+
+/// Identifies a node of a loaded yaml document by its address.
+/// Nodes that are not part of the document (like the `BadValue` for missing
+/// keys) cannot be resolved and are reported without a location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Location(usize);
+
+impl Location {
+    pub fn of(node: &Yaml) -> Self {
+        Self(node as *const Yaml as usize)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Error {
+    pub message: String,
+    pub location: Location,
+}
+
+impl Error {
+    pub fn new<S: Into<String>>(location: Location, message: S) -> Self {
+        Self {
+            message: message.into(),
+            location,
+        }
+    }
+    /// An error caused by the given node
+    pub fn at<S: Into<String>>(node: &Yaml, message: S) -> Self {
+        Self::new(Location::of(node), message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Describes a value for error messages, e.g. `'pa0'`, `5` or `a list`
+pub(crate) fn describe(node: &Yaml) -> String {
+    match node {
+        Yaml::String(string) => format!("'{}'", string),
+        Yaml::Real(number) => number.clone(),
+        Yaml::Integer(number) => number.to_string(),
+        Yaml::Boolean(boolean) => boolean.to_string(),
+        Yaml::Array(_) => "a list".to_string(),
+        Yaml::Hash(_) => "a map".to_string(),
+        Yaml::Null => "an empty value".to_string(),
+        Yaml::Alias(_) | Yaml::BadValue => "a missing value".to_string(),
+    }
+}
+
+/// The kind of the parser events that start a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeEvent {
+    Scalar,
+    Collection,
+    /// A reference to an anchored node, which is a copy of that node
+    /// in the loaded document
+    Alias,
+}
+
+#[derive(Default)]
+struct NodeEvents(Vec<(NodeEvent, Marker)>);
+
+impl MarkedEventReceiver for NodeEvents {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        let kind = match event {
+            Event::Scalar(..) => NodeEvent::Scalar,
+            Event::SequenceStart(_) | Event::MappingStart(_) => NodeEvent::Collection,
+            Event::Alias(_) => NodeEvent::Alias,
+            _ => return,
+        };
+        self.0.push((kind, marker));
+    }
+}
+
+/// The collections that are open while the parser walks through a document
+enum Frame {
+    Sequence,
+    /// The keys seen so far and whether the next node is a key
+    Mapping(HashSet<String>, bool),
+}
+
+/// Collects the keys that are repeated in a mapping
+#[derive(Default)]
+struct DuplicateKeys {
+    frames: Vec<Frame>,
+    duplicates: Vec<(String, Marker)>,
+}
+
+impl MarkedEventReceiver for DuplicateKeys {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        match event {
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.frames.pop();
+                return;
+            }
+            Event::Scalar(..)
+            | Event::Alias(_)
+            | Event::SequenceStart(_)
+            | Event::MappingStart(_) => {}
+            _ => return,
+        }
+        if let Some(Frame::Mapping(keys, next_is_key)) = self.frames.last_mut() {
+            if let (true, Event::Scalar(key, ..)) = (*next_is_key, &event) {
+                if !keys.insert(key.clone()) {
+                    self.duplicates.push((key.clone(), marker));
+                }
+            }
+            *next_is_key = !*next_is_key;
+        }
+        match event {
+            Event::SequenceStart(_) => self.frames.push(Frame::Sequence),
+            Event::MappingStart(_) => self.frames.push(Frame::Mapping(HashSet::new(), true)),
+            _ => {}
+        }
+    }
+}
+
+/// The keys that are repeated in a mapping of the first document with their
+/// position. The loader only keeps one of the values, so they are errors.
+pub(crate) fn duplicate_keys(contents: &str) -> Vec<(String, Marker)> {
+    let mut keys = DuplicateKeys::default();
+    // syntax errors are already reported by the loader
+    match Parser::new(contents.chars()).load(&mut keys, false) {
+        Ok(()) => keys.duplicates,
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The positions of the nodes of a loaded yaml document
+pub(crate) struct Markers(HashMap<Location, Marker>);
+
+impl Markers {
+    /// The parser emits the nodes in the same order as a pre-order traversal
+    /// of the loaded document visits them, so both can be zipped. If their
+    /// numbers differ (e.g. for a duplicate key), the positions would be
+    /// shifted, so the errors are reported without them.
+    pub fn of(contents: &str, document: &Yaml) -> Self {
+        let mut events = NodeEvents::default();
+        let mut markers = HashMap::new();
+        // syntax errors are already reported by the loader
+        if Parser::new(contents.chars())
+            .load(&mut events, false)
+            .is_ok()
+        {
+            let mut events = events.0.iter();
+            let zipped = Self::visit(document, &mut events, &mut markers);
+            if zipped.is_none() || events.next().is_some() {
+                markers.clear();
+            }
+        }
+        Self(markers)
+    }
+    /// Returns `None` if the events ran out before the nodes
+    fn visit<'a>(
+        node: &Yaml,
+        events: &mut impl Iterator<Item = &'a (NodeEvent, Marker)>,
+        markers: &mut HashMap<Location, Marker>,
+    ) -> Option<()> {
+        let (kind, marker) = events.next()?;
+        markers.insert(Location::of(node), *marker);
+        if *kind == NodeEvent::Alias {
+            return Some(());
+        }
+        match node {
+            Yaml::Array(items) => {
+                for item in items {
+                    Self::visit(item, events, markers)?;
+                }
+            }
+            Yaml::Hash(hash) => {
+                for (key, value) in hash {
+                    Self::visit(key, events, markers)?;
+                    Self::visit(value, events, markers)?;
+                }
+                // the parser marks block mappings after their first key,
+                // so they are reported at the first key instead
+                if let Some(marker) = hash
+                    .keys()
+                    .next()
+                    .and_then(|key| markers.get(&Location::of(key)))
+                {
+                    markers.insert(Location::of(node), *marker);
+                }
+            }
+            _ => {}
+        }
+        Some(())
+    }
+    pub fn get(&self, location: Location) -> Option<Marker> {
+        self.0.get(&location).copied()
+    }
+}
+
+/// Formats the message with the position of the marker in the source:
+/// ```text
+/// message
+///  --> origin:line:column
+///   |
+/// 3 |     - pa0
+///   |       ^
+/// ```
+pub(crate) fn render(
+    message: &str,
+    marker: Option<Marker>,
+    origin: &str,
+    contents: &str,
+) -> String {
+    let marker = match marker {
+        Some(marker) => marker,
+        None => return format!("{}\n --> {}", message, origin),
+    };
+    let line_number = marker.line().to_string();
+    let indent = " ".repeat(line_number.len());
+    let line = contents
+        .lines()
+        .nth(marker.line().saturating_sub(1))
+        .unwrap_or("");
+    format!(
+        "{message}\n{indent}--> {origin}:{line_nr}:{column}\n{indent} |\n{line_nr} | {line}\n{indent} | {caret}^",
+        message = message,
+        indent = indent,
+        origin = origin,
+        line_nr = line_number,
+        column = marker.col() + 1,
+        line = line,
+        caret = " ".repeat(marker.col()),
+    )
+}
+
+#[test]
+fn markers_test() {
+    let contents = "
+kind: stm32f1xx
+gpio:
+  input:
+    - pa0:
+      mode: pull_up
+";
+    let document = yaml_rust::YamlLoader::load_from_str(contents).unwrap();
+    let markers = Markers::of(contents, &document[0]);
+    let mode = &document[0]["gpio"]["input"][0]["mode"];
+    let marker = markers.get(Location::of(mode)).unwrap();
+    assert_eq!((marker.line(), marker.col()), (6, 12));
+    assert_eq!(
+        render("Unable to parse mode", Some(marker), "device.yaml", contents),
+        "Unable to parse mode\n --> device.yaml:6:13\n  |\n6 |       mode: pull_up\n  |             ^"
+    );
+    assert_eq!(markers.get(Location::of(&document[0]["missing"])), None);
+}
+
+#[test]
+fn duplicate_keys_test() {
+    let contents = "
+kind: stm32f1xx
+gpio:
+  input:
+    - pa0:
+      mode: pull_up
+      mode: floating
+kind: stm32f4xx
+";
+    let duplicates: Vec<(String, (usize, usize))> = duplicate_keys(contents)
+        .into_iter()
+        .map(|(key, marker)| (key, (marker.line(), marker.col())))
+        .collect();
+    assert_eq!(
+        duplicates,
+        vec![("mode".to_string(), (7, 6)), ("kind".to_string(), (8, 0))]
+    );
+    // the shifted positions are dropped instead of reporting wrong ones
+    let document = yaml_rust::YamlLoader::load_from_str(contents).unwrap();
+    let markers = Markers::of(contents, &document[0]);
+    assert_eq!(markers.get(Location::of(&document[0]["gpio"])), None);
+}
@@ -1,7 +1,10 @@
-use std::{fs, path::PathBuf};
+use std::{fs, path::Path};
 
+mod args;
+mod error;
 // mod config;
 mod device;
+use args::Args;
 use device::DeviceConfig;
 // mod generation;
 // mod types;
@@ -13,60 +16,302 @@ use syn::{parse_macro_input, ItemStruct};
 
 #[proc_macro_attribute]
 pub fn device_config(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let mut path = project_root::get_project_root().expect("Unable to find project root");
-    path.push("device.yaml");
-
-    let config = parse_yaml(&path);
+    let args = parse_macro_input!(attr as Args);
+    let (config, rebuild_triggers) = read_config(&args);
     let item_struct = parse_macro_input!(item as ItemStruct);
     let struct_name = item_struct.ident.clone();
+    // the struct is kept so that its uses do not add follow up errors
+    let config = match config {
+        Ok(config) => config,
+        Err(errors) => {
+            return quote!(
+                #(#rebuild_triggers)*
+                #item_struct
+                #(compile_error!(#errors);)*
+            )
+            .into()
+        }
+    };
 
-    let (init_statements, return_type) = config.get_init_fn();
+    let (init_statements, return_type, component_structs) = config.get_init_fn(&item_struct);
+    let interrupt_unmasks = config.get_interrupt_unmasks();
+    let clock_consts = config.get_clock_consts();
     quote!(
+        #(#rebuild_triggers)*
         #item_struct
+        #(#component_structs)*
         impl #struct_name{
+            #(#clock_consts)*
             fn init() -> #return_type{
-                use core::mem::MaybeUninit;
                 #(#init_statements)*
                 //#return_statement
             }
-            // #[inline]
-            // fn enable_interrupts() {
-            //     unsafe{
-            //         #(#interrupt_unmasks)*
-            //     }
-            // }
+            #[inline]
+            fn enable_interrupts() {
+                unsafe{
+                    #(#interrupt_unmasks)*
+                }
+            }
         }
     )
     .into()
 }
 
-pub(crate) fn parse_yaml(path: &PathBuf) -> DeviceConfig {
-    let contents =
-        fs::read_to_string(path.clone()).expect("Unable to read device.yaml in project root");
-    let parsed_yaml = match yaml_rust::YamlLoader::load_from_str(&contents) {
-        Ok(yaml) => yaml,
-        Err(e) => {
-            let location = e.marker();
-            let line = contents.lines().nth(location.line() - 1).unwrap();
-            let mut message = String::new();
-            // path/device.yaml:line:column
-            message.push_str(&format!("--> {:?}:\n", path,));
-            // line_nr | yaml
-            message.push_str(&format!("{} | {}\n", location.line(), line));
-            for _ in 0..location.col() + location.line().to_string().len() + 3 {
-                message.push(' ');
-            }
-            message.push('^');
-            panic!("Unable to parse yaml:\n{}\n{}", e, message);
+/// Parses the inline description or the one of the selected file together
+/// with the items that rerun the expansion when the selection or the file changes
+fn read_config(args: &Args) -> (Result<DeviceConfig, Vec<String>>, Vec<syn::Item>) {
+    match &args.yaml {
+        Some(yaml) => (parse_yaml_str(&yaml.value(), "inline yaml"), Vec::new()),
+        None => {
+            let path = args.config_path();
+            let mut triggers = Vec::new();
+            // an unreadable file cannot be included, it is only reported once
+            let config = read_yaml(&path).and_then(|contents| {
+                triggers.push(rebuild_trigger(&path));
+                parse_yaml_str(&contents, &path.display().to_string())
+            });
+            triggers.extend(args.env_trigger());
+            (config, triggers)
         }
-    };
-    DeviceConfig::from_yaml(&parsed_yaml[0])
+    }
+}
+
+/// Cargo only tracks files that are read by the compiler, so the device
+/// description is included in an unused constant to rerun the expansion
+/// whenever it changes. Unused constants are not part of the binary.
+fn rebuild_trigger(path: &Path) -> syn::Item {
+    let path = path
+        .canonicalize()
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string();
+    syn::parse_quote!(
+        const _: &[u8] = include_bytes!(#path);
+    )
+}
+
+/// Reads the device description
+fn read_yaml(path: &Path) -> Result<String, Vec<String>> {
+    fs::read_to_string(path).map_err(|e| {
+        vec![format!(
+            "Unable to read device description: {}\n --> {}",
+            e,
+            path.display()
+        )]
+    })
+}
+
+/// Parses the device description, 'origin' names its file in error messages.
+/// The errors are rendered with the position of the yaml node that caused them.
+pub(crate) fn parse_yaml_str(contents: &str, origin: &str) -> Result<DeviceConfig, Vec<String>> {
+    let documents = yaml_rust::YamlLoader::load_from_str(contents).map_err(|e| {
+        vec![error::render(
+            &format!("Unable to parse yaml: {}", e),
+            Some(*e.marker()),
+            origin,
+            contents,
+        )]
+    })?;
+    let document = documents
+        .first()
+        .ok_or_else(|| vec![format!("The device description is empty\n --> {}", origin)])?;
+    let duplicates = error::duplicate_keys(contents);
+    if !duplicates.is_empty() {
+        return Err(duplicates
+            .into_iter()
+            .map(|(key, marker)| {
+                error::render(
+                    &format!("Duplicate key '{}'", key),
+                    Some(marker),
+                    origin,
+                    contents,
+                )
+            })
+            .collect());
+    }
+    DeviceConfig::from_yaml(document).map_err(|errors| {
+        let markers = error::Markers::of(contents, document);
+        errors
+            .iter()
+            .map(|error| {
+                error::render(
+                    &error.message,
+                    markers.get(error.location),
+                    origin,
+                    contents,
+                )
+            })
+            .collect()
+    })
 }
 
 #[test]
-fn generate_test() {
+fn rebuild_trigger_test() {
     let mut path = project_root::get_project_root().expect("Unable to find project root");
-    path.push("notes/yamlLayouts.yaml");
-    let config = parse_yaml(&path);
-    let (init_statements, return_type) = config.get_init_fn();
+    path.push("notes/../notes/yamlLayouts.yaml");
+    let trigger = rebuild_trigger(&path);
+    let mut canonical = project_root::get_project_root().unwrap();
+    canonical.push("notes/yamlLayouts.yaml");
+    let canonical = canonical.display().to_string();
+    assert_eq!(
+        quote!(#trigger).to_string(),
+        quote!(
+            const _: &[u8] = include_bytes!(#canonical);
+        )
+        .to_string()
+    );
+}
+
+#[test]
+fn read_config_test() {
+    let args: Args = syn::parse_quote!(path = "notes/yamlLayouts.yaml");
+    let (config, triggers) = read_config(&args);
+    assert!(config.is_ok());
+    assert_eq!(triggers.len(), 1);
+    let args: Args = syn::parse_quote!(path = "notes/missing.yaml");
+    let (config, triggers) = read_config(&args);
+    let errors = config.err().unwrap();
+    assert!(errors[0].starts_with("Unable to read device description"));
+    assert!(triggers.is_empty());
+}
+
+#[test]
+fn inline_yaml_test() {
+    let args: Args = syn::parse_quote!(
+        yaml = r#"
+kind: stm32f1xx
+clock: 8mhz
+gpio:
+  output:
+    - pc13: push_pull
+      name: led
+"#
+    );
+    let config = parse_yaml_str(&args.yaml.unwrap().value(), "inline yaml").unwrap();
+    let item_struct: ItemStruct = syn::parse_quote!(
+        struct Board;
+    );
+    let (_, _, component_structs) = config.get_init_fn(&item_struct);
+    assert!(quote!(#(#component_structs)*)
+        .to_string()
+        .contains("struct BoardOutputs { pub led :"));
+}
+
+#[test]
+fn generate_test() {
+    let args: Args = syn::parse_quote!(path = "notes/yamlLayouts.yaml");
+    let config = read_config(&args).0.unwrap();
+    let item_struct: ItemStruct = syn::parse_quote!(
+        pub struct Board;
+    );
+    let (init_statements, return_type, component_structs) = config.get_init_fn(&item_struct);
+    let init = quote!(#(#init_statements)*).to_string();
+    let return_type = quote!(#return_type).to_string();
+    let component_structs = quote!(#(#component_structs)*).to_string();
+    assert!(init.contains(
+        "let clocks = cfgr . sysclk (36000000u32 . hz ()) . freeze (& mut flash . acr) ;"
+    ));
+    assert!(init.contains("stm32f1xx_hal :: timer :: Timer :: tim1 (peripherals . TIM1 , & clocks , & mut rcc . apb2)"));
+    assert!(init.contains("stm32f1xx_hal :: timer :: Timer :: tim3 (peripherals . TIM3 , & clocks , & mut rcc . apb1)"));
+    assert!(init
+        .contains("pa1 . set_duty ((pa1 . get_max_duty () as u32 * 7500u32 / 10000u32) as u16)"));
+    assert!(init.contains(". pwm :: < stm32f1xx_hal :: timer :: Tim2NoRemap , _ , _ , _ > (pa1 , & mut afio . mapr , 10000u32 . hz ()) . split ()"));
+    assert!(init.contains("baudrate (9600u32 . bps ()) . parity_even () . stopbits (stm32f1xx_hal :: serial :: StopBits :: STOP1) . wordlength_9bits ()"));
+    assert!(init.contains("let (mut usart1_tx , mut usart1_rx) = usart1 . split ()"));
+    assert!(init.contains(
+        "let mut status_led = gpioc . pc13 . into_push_pull_output (& mut gpioc . crh) ;"
+    ));
+    assert!(
+        init.contains("let mut pc12 = gpioc . pc12 . into_open_drain_output (& mut gpioc . crh) ;")
+    );
+    assert!(init.contains("button . make_interrupt_source (& mut afio) ;"));
+    assert!(init.contains("usart1_rx . listen ()"));
+    let clock_consts = config.get_clock_consts();
+    let clock_consts = quote!(#(#clock_consts)*).to_string();
+    assert!(clock_consts.contains("const SYSCLK_HZ : u32 = 36000000u32 ;"));
+    assert!(clock_consts.contains("const PCLK1_HZ : u32 = 36000000u32 ;"));
+    assert!(clock_consts.contains("const FLASH_LATENCY : u8 = 1u8 ;"));
+    let interrupt_unmasks = config.get_interrupt_unmasks();
+    assert_eq!(
+        quote!(#(#interrupt_unmasks)*).to_string(),
+        quote!(
+            let mut nvic = stm32f1xx_hal::pac::CorePeripherals::steal().NVIC;
+            nvic.set_priority(stm32f1xx_hal::pac::Interrupt::EXTI0, 16u8);
+            nvic.set_priority(stm32f1xx_hal::pac::Interrupt::USART1, 32u8);
+            stm32f1xx_hal::pac::NVIC::unmask(stm32f1xx_hal::pac::Interrupt::EXTI0);
+            stm32f1xx_hal::pac::NVIC::unmask(stm32f1xx_hal::pac::Interrupt::USART1);
+        )
+        .to_string()
+    );
+    assert_eq!(return_type, "& 'static mut BoardComponents");
+    assert!(component_structs.contains(
+        "pub struct BoardComponents { pub inputs : BoardInputs , pub outputs : BoardOutputs , pub timers : BoardTimers , pub pwm : BoardPwm , pub serial : BoardSerial , }"
+    ));
+    assert!(component_structs.contains(
+        "pub button : stm32f1xx_hal :: gpio :: gpioa :: PA0 < stm32f1xx_hal :: gpio :: Input < stm32f1xx_hal :: gpio :: PullUp > >"
+    ));
+    assert!(component_structs.contains(
+        "pub status_led : stm32f1xx_hal :: gpio :: gpioc :: PC13 < stm32f1xx_hal :: gpio :: Output < stm32f1xx_hal :: gpio :: PushPull > >"
+    ));
+    assert!(component_structs.contains(
+        "pub pc12 : stm32f1xx_hal :: gpio :: gpioc :: PC12 < stm32f1xx_hal :: gpio :: Output < stm32f1xx_hal :: gpio :: OpenDrain > >"
+    ));
+    assert!(component_structs
+        .contains("pub tim1 : stm32f1xx_hal :: timer :: Timer < stm32f1xx_hal :: pac :: TIM1 >"));
+    assert!(component_structs.contains(
+        "pub pa1 : stm32f1xx_hal :: pwm :: PwmChannel < stm32f1xx_hal :: pac :: TIM2 , stm32f1xx_hal :: pwm :: C2 >"
+    ));
+    assert!(component_structs.contains(
+        "pub usart1_rx : stm32f1xx_hal :: serial :: Rx < stm32f1xx_hal :: pac :: USART1 >"
+    ));
+    // components keep the order of the yaml
+    let button = component_structs.find("pub button :").unwrap();
+    let pa2 = component_structs.find("pub pa2 :").unwrap();
+    let pa3 = component_structs.find("pub pa3 :").unwrap();
+    assert!(button < pa2 && pa2 < pa3);
+    let tim1 = component_structs.find("pub tim1 :").unwrap();
+    let tim3 = component_structs.find("pub tim3 :").unwrap();
+    assert!(tim1 < tim3);
+    let (init_statements, _, _) = read_config(&args).0.unwrap().get_init_fn(&item_struct);
+    assert_eq!(init, quote!(#(#init_statements)*).to_string());
+    assert!(init.contains(
+        "static mut COMPONENTS : MaybeUninit < BoardComponents > = MaybeUninit :: uninit () ;"
+    ));
+}
+
+#[test]
+fn located_error_test() {
+    let contents = "
+kind: stm32f1xx
+gpio:
+  input:
+    - pa0:
+      mode: pull_sideways
+    - pa1:
+      mode: floating
+      priority: 3
+serial:
+  - usart1:
+    tx: pa9
+    baud: 9600
+";
+    let errors = parse_yaml_str(contents, "device.yaml").unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            "Unable to parse mode 'pull_sideways' (pull_up, pull_down or floating)\n --> device.yaml:6:13\n  |\n6 |       mode: pull_sideways\n  |             ^",
+            "Gpio 'pa1' has a priority but does not interrupt\n --> device.yaml:9:17\n  |\n9 |       priority: 3\n  |                 ^",
+            "Missing key 'rx' in serial\n  --> device.yaml:11:5\n   |\n11 |   - usart1:\n   |     ^",
+        ]
+    );
+    assert_eq!(
+        parse_yaml_str("kind: bluepill: stm32f1xx", "device.yaml").unwrap_err()[0]
+            .lines()
+            .nth(1),
+        Some(" --> device.yaml:1:15")
+    );
+    assert_eq!(
+        parse_yaml_str("kind: stm32f1xx\nkind: stm32f4xx", "device.yaml").unwrap_err(),
+        vec!["Duplicate key 'kind'\n --> device.yaml:2:1\n  |\n2 | kind: stm32f4xx\n  | ^"]
+    );
 }
@@ -0,0 +1,94 @@
+//! Compiles the init function of the host against `embedded-hal-mock` and
+//! uses its components like application code would.
+use embedded_hal::{
+    blocking::delay::DelayMs,
+    digital::v2::{InputPin, OutputPin},
+    serial::Write,
+    PwmPin,
+};
+use embedded_hal_mock::eh0::{
+    digital::{State, Transaction as PinTransaction, TransactionKind},
+    serial::Transaction as SerialTransaction,
+};
+use embedded_rust_hardware_init::device_config;
+
+#[device_config(yaml = r#"
+kind: host
+clock: 72mhz
+gpio:
+  input:
+    - button:
+      mode: pull_up
+      interrupt: falling
+      priority: 1
+  output:
+    - led: push_pull
+      name: status
+pwm:
+  - fan:
+    pins:
+      - fan_a
+      - fan_b
+    duty: 25%
+serial:
+  - uart0:
+    tx: tx
+    rx: rx
+    baud: 115200
+    interrupt: rx
+timer:
+  - delay
+"#)]
+struct Board;
+
+#[test]
+fn init_test() {
+    let mut components = Board::init();
+    Board::enable_interrupts();
+    assert_eq!(Board::SYSCLK_HZ, 72_000_000);
+
+    let button = &mut components.inputs.button;
+    button.update_expectations(&[PinTransaction::get(State::Low)]);
+    assert!(button.is_low().unwrap());
+    button.done();
+
+    let status = &mut components.outputs.status;
+    status.update_expectations(&[PinTransaction::set(State::High)]);
+    status.set_high().unwrap();
+    status.done();
+
+    let [fan_a, fan_b] = &mut components.pwm.fan;
+    fan_a.update_expectations(&[PinTransaction::new(TransactionKind::Enable)]);
+    fan_a.enable();
+    fan_a.done();
+    fan_b.done();
+
+    let uart0 = &mut components.serial.uart0;
+    uart0.update_expectations(&[SerialTransaction::write(b'x')]);
+    uart0.write(b'x').unwrap();
+    uart0.done();
+
+    components.timers.delay.delay_ms(10u32);
+}
+
+/// Every call returns its own components, so tests can run in parallel
+#[test]
+fn parallel_init_test() {
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            std::thread::spawn(|| {
+                let mut components = Board::init();
+                let status = &mut components.outputs.status;
+                status.update_expectations(&[PinTransaction::set(State::Low)]);
+                status.set_low().unwrap();
+                status.done();
+                components.inputs.button.done();
+                components.pwm.fan.iter_mut().for_each(|pin| pin.done());
+                components.serial.uart0.done();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}